@@ -0,0 +1,97 @@
+//! Per-device A/B(/C/D) comparison slots.
+//!
+//! Each slot holds a full `EqState`. When switching, the slot can be loudness matched: its preamp
+//! is lowered so every filled slot has the same perceived loudness as the quietest one, otherwise
+//! the louder EQ tends to win the comparison.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{AppError, ErrorType};
+use crate::filters::EqState;
+use crate::response;
+
+pub const AB_SLOTS_FILE: &str = "ab_slots.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    A,
+    B,
+    C,
+    D,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct AbSlots {
+    pub slots: BTreeMap<Slot, EqState>,
+    pub active: Option<Slot>,
+}
+
+/// Slots of every device, keyed by mapping section name.
+pub type DeviceAbSlots = HashMap<String, AbSlots>;
+
+impl AbSlots {
+
+    /// Gain (in dB, never positive) to add to each slot's preamp so all slots sound equally loud.
+    pub fn loudness_offsets(&self, sample_rate: f64) -> BTreeMap<Slot, f64> {
+        let loudness: BTreeMap<Slot, f64> = self.slots
+            .iter()
+            .map(|(slot, eq)| (*slot, response::perceived_loudness(eq, sample_rate)))
+            .collect();
+        let quietest = loudness.values().cloned().fold(f64::INFINITY, f64::min);
+        loudness.into_iter().map(|(slot, l)| (slot, quietest - l)).collect()
+    }
+
+    /// The EQ to write for a slot, with its loudness offset folded into the preamp if requested.
+    pub fn output_for(&self, slot: Slot, loudness_match: bool, sample_rate: f64) -> Result<EqState, AppError> {
+        let mut eq = self.slots
+            .get(&slot)
+            .cloned()
+            .ok_or(AppError { err_type: ErrorType::BadArguments, message: format!("Slot {:?} is empty", slot) })?;
+        if loudness_match {
+            eq.preamp += self.loudness_offsets(sample_rate).get(&slot).cloned().unwrap_or(0.0);
+        }
+        Ok(eq)
+    }
+
+    /// The EQ to store when capturing a bank that holds `current`. While the bank still holds what
+    /// switching to the active slot wrote, that slot's own EQ is taken so a loudness offset never
+    /// ends up stored in a slot.
+    pub fn capture(&self, current: &EqState, sample_rate: f64) -> EqState {
+        match self.active {
+            Some(active) if self.output_for(active, true, sample_rate).ok().as_ref() == Some(current) => self.slots[&active].clone(),
+            _ => current.clone(),
+        }
+    }
+}
+
+#[test]
+fn test_loudness_matching() {
+    use crate::filters::{FilterParams, FilterType};
+
+    let flat = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    let boosted = EqState {
+        preamp: 0.0,
+        filters: vec![FilterParams { id: "1".to_string(), frequency: 2000.0, gain: 6.0, q: 0.5, filter_type: FilterType::Peaking }],
+        graphic_eq: vec![],
+    };
+    let mut slots = AbSlots::default();
+    slots.slots.insert(Slot::A, flat);
+    slots.slots.insert(Slot::B, boosted);
+
+    let offsets = slots.loudness_offsets(response::DEFAULT_SAMPLE_RATE);
+    assert_eq!(offsets[&Slot::A], 0.0);
+    assert!(offsets[&Slot::B] < -1.0);
+
+    let matched = slots.output_for(Slot::B, true, response::DEFAULT_SAMPLE_RATE).unwrap();
+    let a = response::perceived_loudness(&slots.output_for(Slot::A, true, response::DEFAULT_SAMPLE_RATE).unwrap(), response::DEFAULT_SAMPLE_RATE);
+    let b = response::perceived_loudness(&matched, response::DEFAULT_SAMPLE_RATE);
+    assert!((a - b).abs() < 1e-6);
+    assert!(slots.output_for(Slot::C, true, response::DEFAULT_SAMPLE_RATE).is_err());
+
+    slots.active = Some(Slot::B);
+    assert_eq!(slots.capture(&matched, response::DEFAULT_SAMPLE_RATE), slots.slots[&Slot::B]);
+    let edited = EqState { preamp: -1.0, ..slots.slots[&Slot::B].clone() };
+    assert_eq!(slots.capture(&edited, response::DEFAULT_SAMPLE_RATE), edited);
+}
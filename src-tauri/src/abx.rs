@@ -0,0 +1,212 @@
+//! Blind ABX testing between two EQ configurations.
+//!
+//! For every trial X is randomly assigned to either A or B. The listener can switch between A, B
+//! and X as often as they like and then answers which one X was. Which configuration X is stays in
+//! the backend until the session is finished.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{AppError, ErrorType};
+use crate::filters::EqState;
+
+pub const ABX_DIR: &str = "abx";
+const MAX_TRIALS: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AbxSource {
+    A,
+    B,
+    X,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AbxTrial {
+    pub x_is_a: bool,
+    pub answer: Option<AbxSource>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AbxSession {
+    pub id: String,
+    pub device: String,
+    pub a: EqState,
+    pub b: EqState,
+    pub trial_count: usize,
+    pub trials: Vec<AbxTrial>,
+    pub created: u64,
+    pub finished: Option<u64>,
+    #[serde(default)]
+    pub aborted: bool,
+}
+
+/// What the frontend is allowed to see of a running session.
+#[derive(Debug, Serialize, Clone)]
+pub struct AbxStatus {
+    pub id: String,
+    pub device: String,
+    pub trial: usize,
+    pub trial_count: usize,
+    pub finished: bool,
+    pub results: Option<AbxResults>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AbxResults {
+    pub trials: usize,
+    pub correct: usize,
+    /// Probability of getting at least this many answers right by guessing.
+    pub p_value: f64,
+    /// 95% Wilson score interval of the listener's hit rate.
+    pub confidence_low: f64,
+    pub confidence_high: f64,
+}
+
+impl AbxSession {
+    pub fn new(device: String, a: EqState, b: EqState, trial_count: usize, now: u64) -> Result<AbxSession, AppError> {
+        if trial_count == 0 || trial_count > MAX_TRIALS {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: format!("Trial count must be between 1 and {}", MAX_TRIALS) });
+        }
+        Ok(AbxSession {
+            id: format!("{}-{:08x}", now, random_u64() as u32),
+            device,
+            a,
+            b,
+            trial_count,
+            trials: vec![AbxTrial { x_is_a: random_u64() & 1 == 0, answer: None }],
+            created: now,
+            finished: None,
+            aborted: false,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    /// The EQ to play for a source in the current trial.
+    pub fn eq_for(&self, source: AbxSource) -> Result<&EqState, AppError> {
+        let trial = self.current_trial()?;
+        Ok(match source {
+            AbxSource::A => &self.a,
+            AbxSource::B => &self.b,
+            AbxSource::X => if trial.x_is_a { &self.a } else { &self.b },
+        })
+    }
+
+    /// Records the answer for the current trial and moves on to the next one.
+    pub fn answer(&mut self, answer: AbxSource, now: u64) -> Result<(), AppError> {
+        if answer == AbxSource::X {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: "The answer must be either A or B".to_string() });
+        }
+        self.current_trial()?;
+        self.trials.last_mut().unwrap().answer = Some(answer);
+        if self.trials.len() == self.trial_count {
+            self.finished = Some(now);
+        } else {
+            self.trials.push(AbxTrial { x_is_a: random_u64() & 1 == 0, answer: None });
+        }
+        Ok(())
+    }
+
+    pub fn abort(&mut self, now: u64) {
+        if !self.is_finished() {
+            self.aborted = true;
+            self.finished = Some(now);
+        }
+    }
+
+    pub fn results(&self) -> AbxResults {
+        let answered: Vec<&AbxTrial> = self.trials.iter().filter(|t| t.answer.is_some()).collect();
+        let correct = answered
+            .iter()
+            .filter(|t| t.answer == Some(if t.x_is_a { AbxSource::A } else { AbxSource::B }))
+            .count();
+        let (confidence_low, confidence_high) = wilson_interval(correct, answered.len());
+        AbxResults {
+            trials: answered.len(),
+            correct,
+            p_value: binomial_p_value(correct, answered.len()),
+            confidence_low,
+            confidence_high,
+        }
+    }
+
+    pub fn status(&self) -> AbxStatus {
+        AbxStatus {
+            id: self.id.clone(),
+            device: self.device.clone(),
+            trial: self.trials.len(),
+            trial_count: self.trial_count,
+            finished: self.is_finished(),
+            results: if self.is_finished() { Some(self.results()) } else { None },
+        }
+    }
+
+    fn current_trial(&self) -> Result<&AbxTrial, AppError> {
+        if self.is_finished() {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: "The ABX session has already finished".to_string() });
+        }
+        Ok(self.trials.last().unwrap())
+    }
+}
+
+/// One-sided probability of at least `correct` hits out of `trials` when guessing (p = 0.5).
+pub fn binomial_p_value(correct: usize, trials: usize) -> f64 {
+    if trials == 0 {
+        return 1.0;
+    }
+    // P(X = i) computed incrementally from P(X = 0) = 0.5^n
+    let mut term = 0.5f64.powi(trials as i32);
+    let mut p = 0.0;
+    for i in 0..=trials {
+        if i >= correct {
+            p += term;
+        }
+        term = term * (trials - i) as f64 / (i + 1) as f64;
+    }
+    p.min(1.0)
+}
+
+fn wilson_interval(correct: usize, trials: usize) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let z = 1.96;
+    let n = trials as f64;
+    let p = correct as f64 / n;
+    let denom = 1.0 + z * z / n;
+    let center = (p + z * z / (2.0 * n)) / denom;
+    let margin = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denom;
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+/// Random bits from the std hasher's per-instance random keys; plenty for shuffling trials.
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0));
+    hasher.finish()
+}
+
+#[test]
+fn test_abx_session() {
+    let a = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    let b = EqState { preamp: -3.0, filters: vec![], graphic_eq: vec![] };
+    let mut session = AbxSession::new("all".to_string(), a, b, 10, 0).unwrap();
+
+    while !session.is_finished() {
+        let x = session.eq_for(AbxSource::X).unwrap().preamp;
+        session.answer(if x == 0.0 { AbxSource::A } else { AbxSource::B }, 1).unwrap();
+    }
+    let results = session.results();
+    assert_eq!(results.trials, 10);
+    assert_eq!(results.correct, 10);
+    assert!((results.p_value - 1.0 / 1024.0).abs() < 1e-12);
+    assert!(session.answer(AbxSource::A, 2).is_err());
+
+    assert!((binomial_p_value(0, 10) - 1.0).abs() < 1e-12);
+    assert!((binomial_p_value(9, 10) - 11.0 / 1024.0).abs() < 1e-12);
+    assert!(AbxSession::new("all".to_string(), EqState::default(), EqState::default(), 0, 0).is_err());
+}
@@ -0,0 +1,205 @@
+//! Rules that switch the EQ when the default output device changes, e.g. a headphone preset as
+//! soon as the headphones become the default.
+//!
+//! A rule matches devices with the same patterns as APO's `Device:` selectors, so it can name an
+//! endpoint GUID, a full device string or a wildcard like `*Headphones*`. The first enabled
+//! matching rule wins; devices without a rule leave the EQ as it is.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{DeviceInfo, Direction};
+use crate::changes::Change;
+use crate::errors::{no_such_section, AppError};
+use crate::filters::{DeviceFilterMapping, ALL_DEVICES};
+use crate::presets::PresetLibrary;
+use crate::selector::DeviceSelector;
+
+pub const SWITCH_RULES_FILE: &str = "switch_rules.json";
+pub const SWITCH_LOG_FILE: &str = "switch_log.json";
+const SWITCH_LOG_LIMIT: usize = 200;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SwitchTarget {
+    /// Loads a saved preset into the section's selected layer.
+    Preset { name: String },
+    /// Enables exactly these layers of the section and disables the rest.
+    Layers { enabled: Vec<String> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SwitchRule {
+    pub name: String,
+    pub device: DeviceSelector,
+    /// Mapping section the target is applied to.
+    #[serde(default = "default_section")]
+    pub section: String,
+    pub target: SwitchTarget,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_section() -> String {
+    ALL_DEVICES.to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct SwitchRules {
+    /// Master switch; rules are kept but not applied while off.
+    pub enabled: bool,
+    pub rules: Vec<SwitchRule>,
+}
+
+impl SwitchRules {
+    pub fn rule_for(&self, device: &DeviceInfo) -> Option<&SwitchRule> {
+        if !self.enabled {
+            return None;
+        }
+        self.rules.iter().find(|r| r.enabled && r.device.matches_device(device))
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        for rule in &self.rules {
+            if rule.device.patterns.is_empty() {
+                return Err(AppError::bad_arguments(format!("Rule {} does not match any device", rule.name)));
+            }
+            if let SwitchTarget::Preset { name } = &rule.target {
+                if name.trim().is_empty() {
+                    return Err(AppError::bad_arguments(format!("Rule {} has no preset", rule.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The changes that bring the mapping to what `rule` asks for. A preset is loaded into a section
+/// that does not exist yet; a layer set needs the section and every named layer to exist.
+pub fn switch_changes(rule: &SwitchRule, mapping: &DeviceFilterMapping, presets: &PresetLibrary) -> Result<Vec<Change>, AppError> {
+    let section = &rule.section;
+    match &rule.target {
+        SwitchTarget::Preset { name } => {
+            let preset = presets.load(name)?;
+            let mut changes = vec![];
+            if !mapping.contains_key(section) {
+                changes.push(Change::AddDevice { device: section.clone(), direction: Direction::Render });
+            }
+            changes.push(Change::SetEq { device: section.clone(), eq: preset.eq });
+            Ok(changes)
+        },
+        SwitchTarget::Layers { enabled } => {
+            let bank = mapping.get(section).ok_or_else(|| no_such_section(section))?;
+            if let Some(missing) = enabled.iter().find(|name| bank.layer_index(name).is_none()) {
+                return Err(AppError::bad_arguments(format!("Could not find layer {} for device {}", missing, section)));
+            }
+            Ok(bank.layers
+                .iter()
+                .filter(|layer| layer.enabled != enabled.contains(&layer.name))
+                .map(|layer| Change::SetLayerEnabled { device: section.clone(), name: layer.name.clone(), enabled: !layer.enabled })
+                .collect())
+        },
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SwitchLogEntry {
+    pub time: u64,
+    pub device_guid: String,
+    pub device_name: String,
+    pub rule: String,
+    pub target: SwitchTarget,
+    /// Why the switch could not be applied, if it failed.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// The most recent switches, newest last.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct SwitchLog {
+    pub entries: VecDeque<SwitchLogEntry>,
+}
+
+impl SwitchLog {
+    pub fn record(&mut self, entry: SwitchLogEntry) {
+        self.entries.push_back(entry);
+        while self.entries.len() > SWITCH_LOG_LIMIT {
+            self.entries.pop_front();
+        }
+    }
+}
+
+
+#[test]
+fn test_switch_rules() {
+    use crate::filters::{EqState, FilterBank, Layer};
+
+    let headphones = DeviceInfo { guid: "{0001}".to_string(), name: "Headphones (USB DAC)".to_string(), apo_installed: true, is_default: true, direction: Direction::Render, format: None };
+    let speakers = DeviceInfo { guid: "{0002}".to_string(), name: "Speakers (Realtek Audio)".to_string(), apo_installed: true, is_default: true, direction: Direction::Render, format: None };
+    let rules = SwitchRules {
+        enabled: true,
+        rules: vec![
+            SwitchRule {
+                name: "Disabled".to_string(),
+                device: DeviceSelector::parse("*"),
+                section: default_section(),
+                target: SwitchTarget::Layers { enabled: vec![] },
+                enabled: false,
+            },
+            SwitchRule {
+                name: "Headphones".to_string(),
+                device: DeviceSelector::parse("Headphones*"),
+                section: default_section(),
+                target: SwitchTarget::Preset { name: "HD 650".to_string() },
+                enabled: true,
+            },
+            SwitchRule {
+                name: "Speakers".to_string(),
+                device: DeviceSelector::parse("{0002}"),
+                section: default_section(),
+                target: SwitchTarget::Layers { enabled: vec!["Room".to_string()] },
+                enabled: true,
+            },
+        ],
+    };
+    assert!(rules.validate().is_ok());
+    assert_eq!(rules.rule_for(&headphones).unwrap().name, "Headphones");
+    assert_eq!(rules.rule_for(&speakers).unwrap().name, "Speakers");
+    assert!(SwitchRules { enabled: false, ..rules.clone() }.rule_for(&speakers).is_none());
+
+    let dir = crate::storage::TestDir::new("switch");
+    let presets = PresetLibrary::new(dir.to_path_buf());
+    let hd650 = EqState { preamp: -6.0, ..EqState::default() };
+    presets.save("HD 650", hd650, None, 1).unwrap();
+
+    let mut mapping = FilterBank::default();
+    let changes = switch_changes(&rules.rules[1], &mapping, &presets).unwrap();
+    let updated = crate::changes::apply_changes(&mapping, &changes).unwrap();
+    assert_eq!(updated.get(ALL_DEVICES).unwrap().eq().preamp, -6.0);
+
+    let bank = mapping.get_mut(ALL_DEVICES).unwrap();
+    bank.layers.push(Layer::new("Room", EqState::default()));
+    bank.layers[1].enabled = false;
+    let changes = switch_changes(&rules.rules[2], &mapping, &presets).unwrap();
+    let updated = crate::changes::apply_changes(&mapping, &changes).unwrap();
+    let enabled: Vec<bool> = updated.get(ALL_DEVICES).unwrap().layers.iter().map(|l| l.enabled).collect();
+    assert_eq!(enabled, vec![false, true]);
+    assert!(switch_changes(&rules.rules[2], &updated, &presets).unwrap().is_empty());
+
+    let missing = SwitchRule { target: SwitchTarget::Layers { enabled: vec!["Night".to_string()] }, ..rules.rules[2].clone() };
+    assert!(switch_changes(&missing, &mapping, &presets).is_err());
+    let missing = SwitchRule { target: SwitchTarget::Preset { name: "Nope".to_string() }, ..rules.rules[1].clone() };
+    assert!(switch_changes(&missing, &mapping, &presets).is_err());
+
+    let mut log = SwitchLog::default();
+    for time in 0..(SWITCH_LOG_LIMIT as u64 + 5) {
+        log.record(SwitchLogEntry { time, device_guid: headphones.guid.clone(), device_name: headphones.name.clone(), rule: "Headphones".to_string(), target: rules.rules[1].target.clone(), error: None });
+    }
+    assert_eq!(log.entries.len(), SWITCH_LOG_LIMIT);
+    assert_eq!(log.entries.front().unwrap().time, 5);
+}
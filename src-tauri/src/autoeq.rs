@@ -0,0 +1,160 @@
+//! Import and export of AutoEQ's `ParametricEQ.txt` and `GraphicEQ.txt` files.
+//!
+//! Both are plain EqualizerAPO config snippets, so parsing goes through the same line processors
+//! as eqplus.txt; only the number formatting of the exports differs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::filters::{self, at_line, EqState, FilterParams, FilterType, GraphicEqPoint};
+use crate::response;
+
+/// Number of points AutoEQ writes to a GraphicEQ.txt.
+const GRAPHIC_EQ_POINTS: usize = 127;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoEqFormat {
+    Parametric,
+    Graphic,
+}
+
+/// Parses either kind of AutoEQ file, depending on whether it contains a `GraphicEQ:` line.
+pub fn import(contents: &str) -> Result<EqState, AppError> {
+    if lines(contents).any(|(_, line)| line.starts_with("GraphicEQ:")) {
+        import_graphic(contents)
+    } else {
+        import_parametric(contents)
+    }
+}
+
+pub fn import_parametric(contents: &str) -> Result<EqState, AppError> {
+    let mut eq = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    for (number, line) in lines(contents) {
+        if line.starts_with("Preamp:") {
+            eq.preamp = filters::process_preamp_line(line).map_err(|e| at_line(number, e))?;
+        } else if line.starts_with("Filter") {
+            let mut filter = filters::process_filter_line(line).map_err(|e| at_line(number, e))?;
+            if filter.frequency <= 0.0 || filter.q <= 0.0 {
+                return Err(at_line(number, AppError::invalid_config(format!("Frequency and Q must be greater than zero: {}", line))));
+            }
+            filter.id = (eq.filters.len() + 1).to_string();
+            eq.filters.push(filter);
+        } else {
+            return Err(at_line(number, AppError::invalid_config(format!("Not a ParametricEQ line: {}", line))));
+        }
+    }
+    if eq.filters.is_empty() {
+        return Err(AppError::invalid_config("The file does not contain any filters".to_string()));
+    }
+    Ok(eq)
+}
+
+pub fn import_graphic(contents: &str) -> Result<EqState, AppError> {
+    let mut graphic_eq: Vec<GraphicEqPoint> = vec![];
+    for (number, line) in lines(contents) {
+        if !line.starts_with("GraphicEQ:") {
+            return Err(at_line(number, AppError::invalid_config(format!("Not a GraphicEQ line: {}", line))));
+        }
+        if !graphic_eq.is_empty() {
+            return Err(at_line(number, AppError::invalid_config("Only one GraphicEQ line is supported".to_string())));
+        }
+        graphic_eq = filters::process_graphic_eq_line(line).map_err(|e| at_line(number, e))?;
+    }
+    if graphic_eq.is_empty() {
+        return Err(AppError::invalid_config("The file does not contain a GraphicEQ line".to_string()));
+    }
+    Ok(EqState { preamp: 0.0, filters: vec![], graphic_eq })
+}
+
+pub fn export(eq: &EqState, format: AutoEqFormat) -> Result<String, AppError> {
+    match format {
+        AutoEqFormat::Parametric => export_parametric(eq),
+        AutoEqFormat::Graphic => Ok(export_graphic(eq, response::DEFAULT_SAMPLE_RATE)),
+    }
+}
+
+/// Writes a ParametricEQ.txt the way AutoEQ formats it (`Fc 105 Hz Gain 5.5 dB Q 0.70`).
+pub fn export_parametric(eq: &EqState) -> Result<String, AppError> {
+    if !eq.graphic_eq.is_empty() {
+        return Err(AppError::bad_arguments("An EQ containing a GraphicEQ curve can only be exported as GraphicEQ".to_string()));
+    }
+    let mut lines = vec![format!("Preamp: {:.1} dB", eq.preamp)];
+    for (i, filter) in eq.filters.iter().enumerate() {
+        lines.push(format!("Filter {}: ON {}", i + 1, parametric_tokens(filter)));
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Samples the full response of the EQ, preamp included, into a single GraphicEQ line.
+pub fn export_graphic(eq: &EqState, sample_rate: f64) -> String {
+    let mut frequencies: Vec<f64> = response::log_frequencies(20.0, 20000.0, GRAPHIC_EQ_POINTS)
+        .into_iter()
+        .map(f64::round)
+        .collect();
+    frequencies.dedup();
+    let points: Vec<GraphicEqPoint> = frequencies
+        .iter()
+        .zip(response::eq_response(eq, &frequencies, sample_rate))
+        .map(|(frequency, gain)| GraphicEqPoint { frequency: *frequency, gain })
+        .collect();
+    filters::graphic_eq_line(&points) + "\n"
+}
+
+fn parametric_tokens(filter: &FilterParams) -> String {
+    let fc = format!("{:.0}", filter.frequency);
+    let gain = format!("{:.1}", filter.gain);
+    let q = format!("{:.2}", filter.q);
+    match filter.filter_type {
+        FilterType::AllPass => format!("AP Fc {} Hz Q {}", fc, q),
+        FilterType::BandPass => format!("BP Fc {} Hz Q {}", fc, q),
+        FilterType::HighPass => format!("HPQ Fc {} Hz Q {}", fc, q),
+        FilterType::HighShelf => format!("HSC Fc {} Hz Gain {} dB Q {}", fc, gain, q),
+        FilterType::LowPass => format!("LPQ Fc {} Hz Q {}", fc, q),
+        FilterType::LowShelf => format!("LSC Fc {} Hz Gain {} dB Q {}", fc, gain, q),
+        FilterType::Notch => format!("NO Fc {} Hz Q {}", fc, q),
+        FilterType::Peaking => format!("PK Fc {} Hz Gain {} dB Q {}", fc, gain, q),
+    }
+}
+
+/// Non-empty, non-comment lines with their 1-based line numbers.
+fn lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+
+#[test]
+fn test_autoeq_round_trip() {
+    let parametric = "Preamp: -6.2 dB\n\
+        Filter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70\n\
+        Filter 2: ON PK Fc 187 Hz Gain -2.7 dB Q 0.43\n\
+        Filter 3: ON HSC Fc 10000 Hz Gain -2.0 dB Q 0.70\n";
+    let eq = import(parametric).unwrap();
+    assert_eq!(eq.preamp, -6.2);
+    assert!(matches!(eq.filters[0].filter_type, FilterType::LowShelf));
+    assert_eq!(eq.filters[2].frequency, 10000.0);
+    assert_eq!(export_parametric(&eq).unwrap(), parametric);
+
+    let graphic = export_graphic(&eq, response::DEFAULT_SAMPLE_RATE);
+    assert!(graphic.starts_with("GraphicEQ: 20 "));
+    let imported = import(&graphic).unwrap();
+    assert_eq!(imported.graphic_eq.len(), GRAPHIC_EQ_POINTS);
+    assert_eq!(imported.graphic_eq.last().unwrap().frequency, 20000.0);
+    let original = response::eq_response(&eq, &[1000.0], response::DEFAULT_SAMPLE_RATE)[0];
+    assert!((filters::graphic_eq_gain(&imported.graphic_eq, 1000.0) - original).abs() < 0.1);
+    assert!(export_parametric(&imported).is_err());
+}
+
+#[test]
+fn test_autoeq_errors() {
+    let e = import_parametric("Preamp: -6.2 dB\nFilter 1: ON XX Fc 105 Hz\n").unwrap_err();
+    assert_eq!(e.message, "Line 2: Unsupported filter type: XX");
+    let e = import_parametric("Preamp: -6.2 dB\n\nFilter 1: ON PK Fc abc Hz Gain 1 dB Q 1\n").unwrap_err();
+    assert!(e.message.starts_with("Line 3: Malformed filter line (invalid frequency)"));
+    assert!(import_parametric("Preamp: -1 dB\n").is_err());
+    assert!(import_graphic("GraphicEQ: 20 1; 30\n").unwrap_err().message.starts_with("Line 1: "));
+}
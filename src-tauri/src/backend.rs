@@ -0,0 +1,378 @@
+//! The platform side of eq+: where EqualizerAPO keeps its config and which devices it runs on.
+//!
+//! Backends are picked at runtime, so the same build can run against the real audio stack or the
+//! development backend (set `EQPLUS_BACKEND` to `windows`, `pipewire`, `wine` or `dev`; the
+//! latter reads its devices from `EQPLUS_DEV_FIXTURE`).
+
+use std::env;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{AppError, ErrorType};
+use crate::filters::DeviceFilterMapping;
+use crate::registry::apo::FxSlot;
+use crate::response;
+
+pub const BACKEND_ENV: &str = "EQPLUS_BACKEND";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Native notifications come in bursts (one per changed property), so they are given this long
+/// to settle before the device list is compared.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Speaker positions in the order of their `SPEAKER_*` bits in a WAVEFORMATEXTENSIBLE channel mask.
+pub const SPEAKER_POSITIONS: [&str; 18] = [
+    "FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC", "BC", "SL", "SR", "TC", "TFL", "TFC", "TFR", "TBL", "TBC", "TBR",
+];
+
+/// Whether an endpoint plays (render) or records (capture) audio.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Render,
+    Capture,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 2] = [Direction::Render, Direction::Capture];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Render => "render",
+            Direction::Capture => "capture",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceInfo {
+    pub guid: String,
+    pub name: String,
+    pub apo_installed: bool,
+    /// Default device of its direction.
+    pub is_default: bool,
+    #[serde(default)]
+    pub direction: Direction,
+    /// `None` when the backend could not read it, e.g. for a device that is in exclusive use.
+    #[serde(default)]
+    pub format: Option<MixFormat>,
+}
+
+impl DeviceInfo {
+    /// The rate the EQ runs at, falling back to the usual one while the format is unknown.
+    pub fn sample_rate(&self) -> f64 {
+        self.format.map_or(response::DEFAULT_SAMPLE_RATE, |f| f64::from(f.sample_rate))
+    }
+}
+
+/// The format the audio engine mixes a device's streams in, which is what the EQ runs at.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct MixFormat {
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    pub channels: u16,
+    /// One `SPEAKER_*` bit per channel; the channels are in bit order (see `SPEAKER_POSITIONS`).
+    pub channel_mask: u32,
+}
+
+impl MixFormat {
+    /// Reads a WAVEFORMATEX, or a WAVEFORMATEXTENSIBLE, as laid out in memory.
+    pub fn from_wave_format(bytes: &[u8]) -> Option<MixFormat> {
+        let u16_at = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |i: usize| bytes.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let channels = u16_at(2)?;
+        let sample_rate = u32_at(4)?;
+        let container_bits = u16_at(14)?;
+        if channels == 0 || sample_rate == 0 {
+            return None;
+        }
+        if u16_at(0)? == WAVE_FORMAT_EXTENSIBLE && u16_at(16)? >= 22 {
+            // samples can be narrower than their container, e.g. 24 bits in 32
+            let valid_bits = u16_at(18)?;
+            let bit_depth = if valid_bits == 0 { container_bits } else { valid_bits };
+            return Some(MixFormat { sample_rate, bit_depth, channels, channel_mask: u32_at(20)? });
+        }
+        Some(MixFormat { sample_rate, bit_depth: container_bits, channels, channel_mask: default_channel_mask(channels) })
+    }
+}
+
+/// The channel mask for the given speaker positions. Unknown positions are left out.
+pub fn channel_mask<S: AsRef<str>>(positions: &[S]) -> u32 {
+    positions
+        .iter()
+        .filter_map(|p| SPEAKER_POSITIONS.iter().position(|s| *s == p.as_ref()))
+        .fold(0, |mask, bit| mask | 1 << bit)
+}
+
+/// What Windows assumes for formats without a channel mask (the `KSAUDIO_SPEAKER_*` layouts).
+fn default_channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        4 => 0x33,
+        6 => 0x3f,
+        8 => 0x63f,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ApoStatus {
+    Installed { config_dir: String },
+    NotInstalled { reason: String },
+}
+
+/// One change to the set of active devices.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeviceEvent {
+    Added { device: DeviceInfo },
+    Removed { device: DeviceInfo },
+    /// Still there, but renamed, with APO (un)installed or running at another format.
+    StateChanged { device: DeviceInfo },
+    /// `None` when no device of that direction is the default any more.
+    DefaultChanged { direction: Direction, device: Option<DeviceInfo> },
+}
+
+/// Called with the events of every change to the set of devices (or one of their properties).
+pub type DeviceChangeCallback = Box<dyn Fn(&[DeviceEvent]) + Send + 'static>;
+
+/// Tells the thread started by `watch_device_list` what to do.
+pub enum WatchSignal {
+    /// Something about the devices changed, compare the device list again.
+    Changed,
+    Stop,
+}
+
+/// A running device watcher, stopped when dropped.
+pub struct Watcher {
+    stop: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl Watcher {
+    pub fn new<F: FnOnce() + Send + 'static>(stop: F) -> Watcher {
+        Watcher { stop: vec![Box::new(stop)] }
+    }
+
+    /// Also runs `cleanup` when the watcher is stopped, after everything registered before it.
+    pub fn and_then<F: FnOnce() + Send + 'static>(mut self, cleanup: F) -> Watcher {
+        self.stop.push(Box::new(cleanup));
+        self
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        for stop in self.stop.drain(..) {
+            stop();
+        }
+    }
+}
+
+pub trait AudioBackend: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// The directory EqualizerAPO reads config.txt from.
+    fn config_dir(&self) -> Result<String, AppError>;
+
+    /// Active output and input devices.
+    fn enumerate(&self) -> Result<Vec<DeviceInfo>, AppError>;
+
+    /// The effect slots of the device EqualizerAPO is registered in. `None` where devices have no
+    /// effect slots, like under PipeWire.
+    fn apo_slots(&self, _device: &DeviceInfo) -> Result<Option<Vec<FxSlot>>, AppError> {
+        Ok(None)
+    }
+
+    /// Pushes a freshly written mapping to the audio stack. EqualizerAPO watches its config files
+    /// itself, so by default there is nothing to do.
+    fn apply(&self, _mapping: &DeviceFilterMapping) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    fn apo_status(&self) -> ApoStatus {
+        match self.config_dir() {
+            Ok(config_dir) => ApoStatus::Installed { config_dir },
+            Err(e) => ApoStatus::NotInstalled { reason: e.message },
+        }
+    }
+
+    /// Starts watching for device changes until the returned watcher is dropped. By default the
+    /// device list is polled; backends with native notifications override this.
+    fn watch(self: Arc<Self>, on_change: DeviceChangeCallback) -> Result<Watcher, AppError> {
+        Ok(watch_device_list(self, on_change, Some(POLL_INTERVAL))?.0)
+    }
+}
+
+/// Compares the device list on a thread whenever the returned sender signals a change (or every
+/// `poll`, if given) and reports the differences.
+pub fn watch_device_list<B: AudioBackend + ?Sized>(backend: Arc<B>, on_change: DeviceChangeCallback, poll: Option<Duration>) -> Result<(Watcher, mpsc::Sender<WatchSignal>), AppError> {
+    let (tx, rx) = mpsc::channel();
+    let mut last = backend.enumerate().unwrap_or_default();
+    let thread = thread::Builder::new()
+        .name(format!("{}-device-watcher", backend.name()))
+        .spawn(move || {
+            loop {
+                let signal = match poll {
+                    Some(interval) => rx.recv_timeout(interval),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match signal {
+                    Ok(WatchSignal::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(WatchSignal::Changed) => {
+                        thread::sleep(SETTLE_TIME);
+                        if rx.try_iter().any(|s| matches!(s, WatchSignal::Stop)) {
+                            return;
+                        }
+                    },
+                    Err(RecvTimeoutError::Timeout) => {},
+                }
+                if let Ok(current) = backend.enumerate() {
+                    let events = device_events(&last, &current);
+                    if !events.is_empty() {
+                        on_change(&events);
+                    }
+                    last = current;
+                }
+            }
+        })?;
+    let stop = tx.clone();
+    let watcher = Watcher::new(move || {
+        let _ = stop.send(WatchSignal::Stop);
+        let _ = thread.join();
+    });
+    Ok((watcher, tx))
+}
+
+/// What happened between two enumerations: removals first, then additions and changes, then the
+/// new defaults.
+pub fn device_events(old: &[DeviceInfo], new: &[DeviceInfo]) -> Vec<DeviceEvent> {
+    let find = |devices: &[DeviceInfo], guid: &str| devices.iter().find(|d| d.guid.eq_ignore_ascii_case(guid)).cloned();
+    let mut events: Vec<DeviceEvent> = old
+        .iter()
+        .filter(|d| find(new, &d.guid).is_none())
+        .map(|d| DeviceEvent::Removed { device: d.clone() })
+        .collect();
+    for device in new {
+        match find(old, &device.guid) {
+            None => events.push(DeviceEvent::Added { device: device.clone() }),
+            Some(before) if before.name != device.name || before.apo_installed != device.apo_installed || before.format != device.format => {
+                events.push(DeviceEvent::StateChanged { device: device.clone() })
+            },
+            Some(_) => {},
+        }
+    }
+    for direction in Direction::ALL {
+        let default = |devices: &[DeviceInfo]| devices.iter().find(|d| d.is_default && d.direction == direction).cloned();
+        let new_default = default(new);
+        if default(old).map(|d| d.guid.to_lowercase()) != new_default.as_ref().map(|d| d.guid.to_lowercase()) {
+            events.push(DeviceEvent::DefaultChanged { direction, device: new_default });
+        }
+    }
+    events
+}
+
+/// The backend named by `EQPLUS_BACKEND`, or the platform's native one.
+pub fn select() -> Result<Arc<dyn AudioBackend>, AppError> {
+    match env::var(BACKEND_ENV).ok().as_deref() {
+        None | Some("") => native(),
+        Some("dev") => Ok(Arc::new(crate::dev::DevBackend::new()?)),
+        Some("pipewire") => Ok(Arc::new(crate::pipewire::PipeWireBackend::new()?)),
+        Some("wine") => Ok(Arc::new(crate::wine::WineBackend::new()?)),
+        #[cfg(windows)]
+        Some("windows") => Ok(Arc::new(crate::win32::Win32Backend)),
+        Some(other) => Err(AppError { err_type: ErrorType::BadArguments, message: format!("Unknown audio backend: {}", other) }),
+    }
+}
+
+#[cfg(windows)]
+fn native() -> Result<Arc<dyn AudioBackend>, AppError> {
+    Ok(Arc::new(crate::win32::Win32Backend))
+}
+
+#[cfg(target_os = "linux")]
+fn native() -> Result<Arc<dyn AudioBackend>, AppError> {
+    match crate::pipewire::PipeWireBackend::new() {
+        Ok(backend) => Ok(Arc::new(backend)),
+        Err(e) => {
+            log::warn!("PipeWire backend unavailable, falling back to dev backend: {}", e);
+            Ok(Arc::new(crate::dev::DevBackend::new()?))
+        },
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn native() -> Result<Arc<dyn AudioBackend>, AppError> {
+    Ok(Arc::new(crate::dev::DevBackend::new()?))
+}
+
+#[test]
+fn test_watch_device_list() {
+    use std::sync::Mutex;
+    use crate::dev::{fixture::{Fixture, Hotplug}, DevBackend};
+
+    let fixture = Fixture::parse(r#"{ "devices": [{ "guid": "{0001}", "name": "Speakers", "is_default": true }] }"#).unwrap();
+    let backend = Arc::new(DevBackend::with_fixture(fixture));
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let (watcher, signals) = watch_device_list(backend.clone(), Box::new(move |events| tx.lock().unwrap().send(events.to_vec()).unwrap()), None).unwrap();
+
+    backend.simulate(&Hotplug::SetApoInstalled { guid: "{0001}".to_string(), installed: false }).unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+    signals.send(WatchSignal::Changed).unwrap();
+    let events = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(&events[..], [DeviceEvent::StateChanged { device }] if !device.apo_installed));
+
+    drop(watcher);
+    assert!(signals.send(WatchSignal::Changed).is_err());
+}
+
+#[test]
+fn test_device_events() {
+    let device = |guid: &str, name: &str, is_default: bool| DeviceInfo { guid: guid.to_string(), name: name.to_string(), apo_installed: true, is_default, direction: Direction::Render, format: None };
+    let old = vec![device("{1}", "Speakers", true), device("{2}", "Headphones", false)];
+
+    assert!(device_events(&old, &old).is_empty());
+    let new = vec![device("{2}", "Headphones (USB)", true), device("{3}", "HDMI", false)];
+    assert_eq!(device_events(&old, &new), vec![
+        DeviceEvent::Removed { device: old[0].clone() },
+        DeviceEvent::StateChanged { device: new[0].clone() },
+        DeviceEvent::Added { device: new[1].clone() },
+        DeviceEvent::DefaultChanged { direction: Direction::Render, device: Some(new[0].clone()) },
+    ]);
+    assert_eq!(device_events(&old[1..], &[]), vec![DeviceEvent::Removed { device: old[1].clone() }]);
+    assert_eq!(device_events(&old[..1], &[device("{1}", "Speakers", false)]), vec![DeviceEvent::DefaultChanged { direction: Direction::Render, device: None }]);
+
+    let mic = DeviceInfo { direction: Direction::Capture, ..device("{4}", "Microphone", true) };
+    assert_eq!(device_events(&old, &[old.clone(), vec![mic.clone()]].concat()), vec![
+        DeviceEvent::Added { device: mic.clone() },
+        DeviceEvent::DefaultChanged { direction: Direction::Capture, device: Some(mic) },
+    ]);
+
+    let resampled = DeviceInfo { format: Some(MixFormat { sample_rate: 96000, bit_depth: 24, channels: 2, channel_mask: 0x3 }), ..old[1].clone() };
+    assert_eq!(device_events(&old[1..], std::slice::from_ref(&resampled)), vec![DeviceEvent::StateChanged { device: resampled.clone() }]);
+}
+
+#[test]
+fn test_mix_format() {
+    // 48 kHz stereo, 24 valid bits in a 32 bit container
+    let extensible = [
+        0xfe, 0xff, 0x02, 0x00, 0x80, 0xbb, 0x00, 0x00, 0x00, 0xdc, 0x05, 0x00, 0x08, 0x00, 0x20, 0x00, 0x16, 0x00,
+        0x18, 0x00, 0x03, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+    ];
+    assert_eq!(MixFormat::from_wave_format(&extensible), Some(MixFormat { sample_rate: 48000, bit_depth: 24, channels: 2, channel_mask: 0x3 }));
+    // plain 44.1 kHz 16 bit mono PCM
+    let pcm = [0x01, 0x00, 0x01, 0x00, 0x44, 0xac, 0x00, 0x00, 0x88, 0x58, 0x01, 0x00, 0x02, 0x00, 0x10, 0x00, 0x00, 0x00];
+    assert_eq!(MixFormat::from_wave_format(&pcm), Some(MixFormat { sample_rate: 44100, bit_depth: 16, channels: 1, channel_mask: 0x4 }));
+    assert_eq!(MixFormat::from_wave_format(&pcm[..10]), None);
+
+    assert_eq!(channel_mask(&["FL", "FR", "FC", "LFE", "BL", "BR"]), 0x3f);
+    assert_eq!(channel_mask(&["FL", "FR", "SL", "SR", "MONO"]), 0x603);
+}
@@ -0,0 +1,123 @@
+//! Blending between EQs and scaling their intensity.
+
+use crate::filters::{self, EqState, FilterParams, FilterType, GraphicEqPoint};
+use crate::response;
+
+/// Interpolates from `a` (amount 0.0) to `b` (amount 1.0).
+///
+/// Filters are matched by id. Frequency and Q are interpolated in log space and gain linearly;
+/// filters only present on one side fade their gain in or out, while ones without a gain switch
+/// over at the halfway point. GraphicEQ curves are blended point by point.
+pub fn morph(a: &EqState, b: &EqState, amount: f64) -> EqState {
+    let t = amount.clamp(0.0, 1.0);
+    let mut filters: Vec<FilterParams> = vec![];
+
+    for fa in &a.filters {
+        match b.filters.iter().find(|fb| fb.id == fa.id) {
+            Some(fb) => filters.push(FilterParams {
+                id: fa.id.clone(),
+                frequency: lerp_log(fa.frequency, fb.frequency, t),
+                gain: lerp(fa.gain, fb.gain, t),
+                q: lerp_log(fa.q, fb.q, t),
+                filter_type: if t < 0.5 { fa.filter_type } else { fb.filter_type },
+            }),
+            None => if let Some(f) = fade(fa, 1.0 - t) {
+                filters.push(f);
+            },
+        }
+    }
+    for fb in b.filters.iter().filter(|fb| !a.filters.iter().any(|fa| fa.id == fb.id)) {
+        if let Some(f) = fade(fb, t) {
+            filters.push(f);
+        }
+    }
+
+    let graphic_eq = filters::combine_graphic_eq(&[(&a.graphic_eq, 1.0 - t), (&b.graphic_eq, t)]);
+    EqState { preamp: lerp(a.preamp, b.preamp, t), filters, graphic_eq }
+}
+
+/// Scales every filter's (and the GraphicEQ curve's) gain by `depth` (1.0 leaves the EQ untouched) and recomputes the preamp
+/// so the scaled EQ does not clip.
+pub fn scale_depth(eq: &EqState, depth: f64, sample_rate: f64) -> EqState {
+    let depth = depth.max(0.0);
+    let filters = eq.filters
+        .iter()
+        .map(|f| FilterParams { gain: f.gain * depth, ..f.clone() })
+        .collect();
+    let graphic_eq = eq.graphic_eq
+        .iter()
+        .map(|p| GraphicEqPoint { gain: p.gain * depth, ..*p })
+        .collect();
+    let mut scaled = EqState { preamp: 0.0, filters, graphic_eq };
+    scaled.preamp = headroom_preamp(&scaled, sample_rate);
+    scaled
+}
+
+/// The preamp needed to keep the peak of the EQ's response at or below 0 dB.
+pub fn headroom_preamp(eq: &EqState, sample_rate: f64) -> f64 {
+    let unity = EqState { preamp: 0.0, ..eq.clone() };
+    let frequencies = response::log_frequencies(20.0, 20000.0, 512);
+    let peak = response::eq_response(&unity, &frequencies, sample_rate)
+        .into_iter()
+        .fold(0.0f64, f64::max);
+    let headroom = (peak * 10.0).ceil() / 10.0;
+    if headroom > 0.0 { -headroom } else { 0.0 }
+}
+
+fn fade(filter: &FilterParams, weight: f64) -> Option<FilterParams> {
+    if uses_gain(filter.filter_type) {
+        if weight <= 0.0 {
+            return None;
+        }
+        Some(FilterParams { gain: filter.gain * weight, ..filter.clone() })
+    } else if weight >= 0.5 {
+        Some(filter.clone())
+    } else {
+        None
+    }
+}
+
+fn uses_gain(filter_type: FilterType) -> bool {
+    matches!(filter_type, FilterType::Peaking | FilterType::LowShelf | FilterType::HighShelf)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn lerp_log(a: f64, b: f64, t: f64) -> f64 {
+    // keep the end points exact so a 0%/100% morph reproduces its input
+    if t <= 0.0 {
+        return a;
+    }
+    if t >= 1.0 {
+        return b;
+    }
+    (a.ln() + (b.ln() - a.ln()) * t).exp()
+}
+
+#[test]
+fn test_morph_and_depth() {
+    let filter = |id: &str, frequency: f64, gain: f64| FilterParams { id: id.to_string(), frequency, gain, q: 1.0, filter_type: FilterType::Peaking };
+    let a = EqState { preamp: 0.0, filters: vec![filter("1", 100.0, 4.0), filter("2", 3000.0, -2.0)], graphic_eq: vec![] };
+    let b = EqState { preamp: -4.0, filters: vec![filter("1", 400.0, 0.0), filter("3", 8000.0, 6.0)], graphic_eq: vec![] };
+
+    let start = morph(&a, &b, 0.0);
+    assert_eq!(start.filters.len(), 2);
+    assert_eq!(start.filters[0].frequency, 100.0);
+
+    let half = morph(&a, &b, 0.5);
+    assert!((half.filters[0].frequency - 200.0).abs() < 1e-9);
+    assert!((half.filters[0].gain - 2.0).abs() < 1e-9);
+    assert!((half.filters[1].gain + 1.0).abs() < 1e-9);
+    assert!((half.filters[2].gain - 3.0).abs() < 1e-9);
+    assert_eq!(half.preamp, -2.0);
+
+    let end = morph(&a, &b, 1.0);
+    assert_eq!(end.filters.iter().map(|f| f.id.as_str()).collect::<Vec<&str>>(), vec!["1", "3"]);
+
+    let scaled = scale_depth(&b, 0.5, response::DEFAULT_SAMPLE_RATE);
+    assert_eq!(scaled.filters[1].gain, 3.0);
+    assert!(scaled.preamp <= -3.0 && scaled.preamp > -3.2);
+    assert_eq!(scale_depth(&b, 0.0, response::DEFAULT_SAMPLE_RATE).preamp, 0.0);
+}
@@ -0,0 +1,451 @@
+//! Batched edits against a `DeviceFilterMapping`.
+//!
+//! Every change in a batch is applied to a copy of the mapping and the result is validated
+//! before anything is handed back, so callers either get a fully updated mapping or an error
+//! and the original is left untouched.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::backend::Direction;
+use crate::errors::{no_such_section, AppError};
+use crate::filters::{DeviceFilterMapping, EqState, FilterBank, FilterParams, Layer, ALL_DEVICES};
+use crate::selector::DeviceSelector;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    ModifyFilter { device: String, filter: FilterParams },
+    AddFilter { device: String, filter: FilterParams },
+    RemoveFilter { device: String, id: String },
+    ModifyPreamp { device: String, preamp: f64 },
+    SetEq { device: String, eq: EqState },
+    /// Capture sections hold a microphone's EQ.
+    AddDevice {
+        device: String,
+        #[serde(default)]
+        direction: Direction,
+    },
+    RemoveDevice { device: String },
+    SetDeviceEnabled { device: String, enabled: bool },
+    RenameDevice { from: String, to: String },
+    CopyDeviceEq { from: String, to: String },
+    AddLayer { device: String, name: String },
+    RemoveLayer { device: String, name: String },
+    RenameLayer { device: String, from: String, to: String },
+    SetLayerEnabled { device: String, name: String, enabled: bool },
+    SetLayerGain { device: String, name: String, gain: f64 },
+    MoveLayer { device: String, name: String, index: usize },
+    SelectLayer { device: String, name: String },
+    SetInheritAll { device: String, inherit: bool },
+}
+
+/// A single RFC 6902 operation, applied against the JSON form of a `DeviceFilterMapping`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+pub fn apply_changes(mapping: &DeviceFilterMapping, changes: &[Change]) -> Result<DeviceFilterMapping, AppError> {
+    let mut updated = mapping.clone();
+    for change in changes {
+        apply_change(&mut updated, change)?;
+    }
+    validate(&updated)?;
+    Ok(updated)
+}
+
+fn apply_change(mapping: &mut DeviceFilterMapping, change: &Change) -> Result<(), AppError> {
+    match change {
+        Change::ModifyFilter { device, filter } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let existing = bank.eq_mut().filters
+                .iter_mut()
+                .find(|f| f.id == filter.id)
+                .ok_or_else(|| AppError::bad_arguments(format!("Could not find filter {} for device {}", filter.id, device)))?;
+            *existing = filter.clone();
+        },
+        Change::AddFilter { device, filter } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            bank.eq_mut().filters.push(filter.clone());
+        },
+        Change::RemoveFilter { device, id } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let filters = &mut bank.eq_mut().filters;
+            let before = filters.len();
+            filters.retain(|f| &f.id != id);
+            if filters.len() == before {
+                return Err(AppError::bad_arguments(format!("Could not find filter {} for device {}", id, device)));
+            }
+        },
+        Change::ModifyPreamp { device, preamp } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            bank.eq_mut().preamp = *preamp;
+        },
+        Change::SetEq { device, eq } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            *bank.eq_mut() = eq.clone();
+        },
+        Change::AddDevice { device, direction } => {
+            if mapping.contains_key(device) {
+                return Err(AppError::bad_arguments(format!("A mapping for device {} already exists", device)));
+            }
+            mapping.insert(device.clone(), FilterBank { direction: *direction, ..FilterBank::new(EqState::default()) });
+        },
+        Change::RemoveDevice { device } => {
+            if device == ALL_DEVICES {
+                return Err(AppError::bad_arguments(format!("The '{}' mapping cannot be removed", ALL_DEVICES)));
+            }
+            mapping.remove(device).ok_or_else(|| no_such_section(device))?;
+        },
+        Change::SetDeviceEnabled { device, enabled } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            bank.enabled = *enabled;
+        },
+        Change::RenameDevice { from, to } => {
+            if from == ALL_DEVICES {
+                return Err(AppError::bad_arguments(format!("The '{}' mapping cannot be renamed", ALL_DEVICES)));
+            }
+            if mapping.contains_key(to) {
+                return Err(AppError::bad_arguments(format!("A mapping for device {} already exists", to)));
+            }
+            let bank = mapping.remove(from).ok_or_else(|| no_such_section(from))?;
+            mapping.insert(to.clone(), bank);
+        },
+        Change::CopyDeviceEq { from, to } => {
+            let source = mapping.get(from).ok_or_else(|| no_such_section(from))?.clone();
+            let bank = mapping.get_mut(to).ok_or_else(|| no_such_section(to))?;
+            bank.layers = source.layers;
+            bank.selected_layer = source.selected_layer;
+        },
+        Change::AddLayer { device, name } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            if bank.layer_index(name).is_some() {
+                return Err(AppError::bad_arguments(format!("Device {} already has a layer named {}", device, name)));
+            }
+            bank.layers.push(Layer::new(name, EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] }));
+            bank.selected_layer = bank.layers.len() - 1;
+        },
+        Change::RemoveLayer { device, name } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let index = find_layer(bank, device, name)?;
+            if bank.layers.len() == 1 {
+                return Err(AppError::bad_arguments(format!("Cannot remove the last layer of device {}", device)));
+            }
+            bank.layers.remove(index);
+            if bank.selected_layer > index || bank.selected_layer == bank.layers.len() {
+                bank.selected_layer -= 1;
+            }
+        },
+        Change::RenameLayer { device, from, to } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            if bank.layer_index(to).is_some() {
+                return Err(AppError::bad_arguments(format!("Device {} already has a layer named {}", device, to)));
+            }
+            let index = find_layer(bank, device, from)?;
+            bank.layers[index].name = to.clone();
+        },
+        Change::SetLayerEnabled { device, name, enabled } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let index = find_layer(bank, device, name)?;
+            bank.layers[index].enabled = *enabled;
+        },
+        Change::SetLayerGain { device, name, gain } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let index = find_layer(bank, device, name)?;
+            bank.layers[index].gain = *gain;
+        },
+        Change::MoveLayer { device, name, index } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            let from = find_layer(bank, device, name)?;
+            if *index >= bank.layers.len() {
+                return Err(AppError::bad_arguments(format!("Invalid layer position {} for device {}", index, device)));
+            }
+            let selected = bank.layers[bank.selected_layer].name.clone();
+            let layer = bank.layers.remove(from);
+            bank.layers.insert(*index, layer);
+            bank.selected_layer = bank.layer_index(&selected).unwrap();
+        },
+        Change::SelectLayer { device, name } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            bank.selected_layer = find_layer(bank, device, name)?;
+        },
+        Change::SetInheritAll { device, inherit } => {
+            let bank = mapping.get_mut(device).ok_or_else(|| no_such_section(device))?;
+            bank.inherit_all = *inherit;
+        },
+    };
+    Ok(())
+}
+
+pub fn apply_patch(mapping: &DeviceFilterMapping, patch: &[PatchOperation]) -> Result<DeviceFilterMapping, AppError> {
+    let mut doc = serde_json::to_value(mapping).map_err(|e| AppError::bad_arguments(format!("Could not serialize mapping: {}", e)))?;
+    for op in patch {
+        apply_patch_operation(&mut doc, op)?;
+    }
+    let updated: DeviceFilterMapping = serde_json::from_value(doc)
+        .map_err(|e| AppError::bad_arguments(format!("Patch produced an invalid mapping: {}", e)))?;
+    validate(&updated)?;
+    Ok(updated)
+}
+
+fn apply_patch_operation(doc: &mut Value, op: &PatchOperation) -> Result<(), AppError> {
+    match op {
+        PatchOperation::Add { path, value } => add_value(doc, path, value.clone()),
+        PatchOperation::Remove { path } => remove_value(doc, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            let target = doc.pointer_mut(path).ok_or_else(|| missing_path(path))?;
+            *target = value.clone();
+            Ok(())
+        },
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(AppError::bad_arguments(format!("Cannot move {} into one of its children", from)));
+            }
+            let value = remove_value(doc, from)?;
+            add_value(doc, path, value)
+        },
+        PatchOperation::Copy { from, path } => {
+            let value = doc.pointer(from).ok_or_else(|| missing_path(from))?.clone();
+            add_value(doc, path, value)
+        },
+        PatchOperation::Test { path, value } => {
+            match doc.pointer(path) {
+                Some(actual) if json_equal(actual, value) => Ok(()),
+                _ => Err(AppError::bad_arguments(format!("Patch test failed at {}", path))),
+            }
+        },
+    }
+}
+
+/// JSON equality that compares numbers by value, so a `0` from the frontend matches a stored `0.0`.
+fn json_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => x.len() == y.len() && x.iter().zip(y).all(|(x, y)| json_equal(x, y)),
+        (Value::Object(x), Value::Object(y)) => x.len() == y.len() && x.iter().all(|(k, v)| y.get(k).is_some_and(|w| json_equal(v, w))),
+        _ => a == b,
+    }
+}
+
+fn add_value(doc: &mut Value, path: &str, value: Value) -> Result<(), AppError> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent_path, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent_path.as_str()).ok_or_else(|| missing_path(path))? {
+        Value::Object(map) => {
+            map.insert(token, value);
+            Ok(())
+        },
+        Value::Array(arr) => {
+            let index = if token == "-" { arr.len() } else { parse_index(&token, arr.len() + 1)? };
+            arr.insert(index, value);
+            Ok(())
+        },
+        _ => Err(missing_path(path)),
+    }
+}
+
+fn remove_value(doc: &mut Value, path: &str) -> Result<Value, AppError> {
+    let (parent_path, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent_path.as_str()).ok_or_else(|| missing_path(path))? {
+        Value::Object(map) => map.remove(&token).ok_or_else(|| missing_path(path)),
+        Value::Array(arr) => {
+            let index = parse_index(&token, arr.len())?;
+            Ok(arr.remove(index))
+        },
+        _ => Err(missing_path(path)),
+    }
+}
+
+/// Splits a JSON pointer into its parent pointer and the unescaped final token.
+fn split_pointer(path: &str) -> Result<(String, String), AppError> {
+    if !path.starts_with('/') {
+        return Err(AppError::bad_arguments(format!("Invalid JSON pointer: {}", path)));
+    }
+    let pos = path.rfind('/').unwrap();
+    let token = path[pos + 1..].replace("~1", "/").replace("~0", "~");
+    Ok((path[..pos].to_string(), token))
+}
+
+fn parse_index(token: &str, len: usize) -> Result<usize, AppError> {
+    match token.parse::<usize>() {
+        Ok(i) if i < len && (token == "0" || !token.starts_with('0')) => Ok(i),
+        _ => Err(AppError::bad_arguments(format!("Invalid array index: {}", token))),
+    }
+}
+
+/// Rejects mappings that would not survive a round trip through the APO config format.
+pub fn validate(mapping: &DeviceFilterMapping) -> Result<(), AppError> {
+    for (device, bank) in mapping {
+        if device.trim().is_empty() || device.contains('\n') {
+            return Err(AppError::bad_arguments(format!("Invalid device name: {:?}", device)));
+        }
+        if !bank.inherit_all {
+            if device == ALL_DEVICES {
+                return Err(AppError::bad_arguments(format!("The '{}' mapping cannot opt out of itself", ALL_DEVICES)));
+            }
+            if DeviceSelector::parse(device).guids().is_empty() {
+                return Err(AppError::bad_arguments(format!("Device {} must be selected by GUID to opt out of '{}'", device, ALL_DEVICES)));
+            }
+        }
+        if bank.layers.is_empty() || bank.selected_layer >= bank.layers.len() {
+            return Err(AppError::bad_arguments(format!("Invalid layers for device {}", device)));
+        }
+        for (i, layer) in bank.layers.iter().enumerate() {
+            if layer.name.trim().is_empty() || layer.name.contains('\n') {
+                return Err(AppError::bad_arguments(format!("Invalid layer name {:?} for device {}", layer.name, device)));
+            }
+            if bank.layers[..i].iter().any(|l| l.name == layer.name) {
+                return Err(AppError::bad_arguments(format!("Duplicate layer name {} for device {}", layer.name, device)));
+            }
+            if !layer.gain.is_finite() {
+                return Err(AppError::bad_arguments(format!("Invalid gain for layer {} on device {}", layer.name, device)));
+            }
+            validate_eq(&layer.eq, device)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_eq(eq: &EqState, device: &str) -> Result<(), AppError> {
+    if !eq.preamp.is_finite() {
+        return Err(AppError::bad_arguments(format!("Invalid preamp for device {}", device)));
+    }
+    let mut seen: Vec<&str> = vec![];
+    for filter in &eq.filters {
+        if filter.id.is_empty() || !filter.id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(AppError::bad_arguments(format!("Invalid filter id '{}' for device {}", filter.id, device)));
+        }
+        if seen.contains(&filter.id.as_str()) {
+            return Err(AppError::bad_arguments(format!("Duplicate filter id {} for device {}", filter.id, device)));
+        }
+        seen.push(filter.id.as_str());
+        if !filter.frequency.is_finite() || filter.frequency <= 0.0 {
+            return Err(AppError::bad_arguments(format!("Invalid frequency for filter {} on device {}", filter.id, device)));
+        }
+        if !filter.q.is_finite() || filter.q <= 0.0 {
+            return Err(AppError::bad_arguments(format!("Invalid Q for filter {} on device {}", filter.id, device)));
+        }
+        if !filter.gain.is_finite() {
+            return Err(AppError::bad_arguments(format!("Invalid gain for filter {} on device {}", filter.id, device)));
+        }
+    }
+    if eq.graphic_eq.iter().any(|p| !p.frequency.is_finite() || p.frequency <= 0.0 || !p.gain.is_finite()) {
+        return Err(AppError::bad_arguments(format!("Invalid GraphicEQ point on device {}", device)));
+    }
+    Ok(())
+}
+
+fn find_layer(bank: &FilterBank, device: &str, name: &str) -> Result<usize, AppError> {
+    bank.layer_index(name).ok_or_else(|| AppError::bad_arguments(format!("Could not find layer {} for device {}", name, device)))
+}
+
+fn missing_path(path: &str) -> AppError {
+    AppError::bad_arguments(format!("Patch path does not exist: {}", path))
+}
+
+
+#[test]
+fn test_apply_changes_is_atomic() {
+    let mapping = crate::filters::FilterBank::default();
+    let filter = |id: &str, frequency: f64| FilterParams { id: id.to_string(), frequency, gain: 1.0, q: 1.0, filter_type: crate::filters::FilterType::Peaking };
+
+    let changes = vec![
+        Change::ModifyPreamp { device: "all".to_string(), preamp: -3.0 },
+        Change::AddFilter { device: "all".to_string(), filter: filter("5", 8000.0) },
+        Change::RemoveFilter { device: "all".to_string(), id: "1".to_string() },
+    ];
+    let updated = apply_changes(&mapping, &changes).unwrap();
+    let eq = updated.get("all").unwrap().eq();
+    assert_eq!(eq.preamp, -3.0);
+    assert_eq!(eq.filters.len(), 4);
+    assert!(eq.filters.iter().all(|f| f.id != "1"));
+
+    let bad = vec![
+        Change::ModifyPreamp { device: "all".to_string(), preamp: -3.0 },
+        Change::AddFilter { device: "all".to_string(), filter: filter("2", 100.0) },
+    ];
+    assert!(apply_changes(&mapping, &bad).is_err());
+    assert_eq!(mapping.get("all").unwrap().eq().preamp, 0.0);
+}
+
+#[test]
+fn test_apply_patch() {
+    let mapping = crate::filters::FilterBank::default();
+    let patch: Vec<PatchOperation> = serde_json::from_str(r#"[
+        { "op": "test", "path": "/all/layers/0/eq/preamp", "value": 0 },
+        { "op": "test", "path": "/all/layers/0/eq/filters/0", "value": { "id": "1", "frequency": 48, "gain": 0, "q": 1, "type": "peaking" } },
+        { "op": "replace", "path": "/all/layers/0/eq/preamp", "value": -2.5 },
+        { "op": "remove", "path": "/all/layers/0/eq/filters/0" },
+        { "op": "copy", "from": "/all", "path": "/other device" }
+    ]"#).unwrap();
+    let updated = apply_patch(&mapping, &patch).unwrap();
+    assert_eq!(updated.get("all").unwrap().eq().preamp, -2.5);
+    assert_eq!(updated.get("all").unwrap().eq().filters.len(), 3);
+    assert_eq!(updated.get("other device").unwrap().eq().filters.len(), 3);
+
+    let failing: Vec<PatchOperation> = serde_json::from_str(r#"[
+        { "op": "test", "path": "/all/layers/0/eq/preamp", "value": 1 }
+    ]"#).unwrap();
+    assert!(apply_patch(&mapping, &failing).is_err());
+    let failing: Vec<PatchOperation> = serde_json::from_str(r#"[
+        { "op": "test", "path": "/all/layers/0/eq/preamp", "value": "0" }
+    ]"#).unwrap();
+    assert!(apply_patch(&mapping, &failing).is_err());
+}
+
+#[test]
+fn test_device_mapping_changes() {
+    let mapping = crate::filters::FilterBank::default();
+    let device = "Speakers Realtek Audio {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}".to_string();
+    assert!(apply_changes(&mapping, &[Change::SetInheritAll { device: ALL_DEVICES.to_string(), inherit: false }]).is_err());
+
+    let changes = vec![
+        Change::AddDevice { device: device.clone(), direction: Direction::Render },
+        Change::ModifyPreamp { device: ALL_DEVICES.to_string(), preamp: -4.0 },
+        Change::CopyDeviceEq { from: ALL_DEVICES.to_string(), to: device.clone() },
+        Change::SetDeviceEnabled { device: device.clone(), enabled: false },
+        Change::RenameDevice { from: device.clone(), to: "Headphones".to_string() },
+    ];
+    let updated = apply_changes(&mapping, &changes).unwrap();
+    assert!(!updated.contains_key(&device));
+    let renamed = updated.get("Headphones").unwrap();
+    assert!(!renamed.enabled);
+    assert_eq!(renamed.eq().preamp, -4.0);
+
+    let removed = apply_changes(&updated, &[Change::RemoveDevice { device: "Headphones".to_string() }]).unwrap();
+    assert_eq!(removed.len(), 1);
+    assert!(apply_changes(&removed, &[Change::RemoveDevice { device: ALL_DEVICES.to_string() }]).is_err());
+}
+
+#[test]
+fn test_layer_changes() {
+    let mapping = crate::filters::FilterBank::default();
+    let all = || ALL_DEVICES.to_string();
+    let changes = vec![
+        Change::AddLayer { device: all(), name: "Bass boost".to_string() },
+        Change::ModifyPreamp { device: all(), preamp: -3.0 },
+        Change::SetLayerGain { device: all(), name: "Bass boost".to_string(), gain: -1.0 },
+        Change::MoveLayer { device: all(), name: "Bass boost".to_string(), index: 0 },
+    ];
+    let updated = apply_changes(&mapping, &changes).unwrap();
+    let bank = updated.get("all").unwrap();
+    assert_eq!(bank.layers[0].name, "Bass boost");
+    assert_eq!(bank.selected_layer, 0);
+    assert_eq!(bank.eq().preamp, -3.0);
+    assert_eq!(bank.flattened().preamp, -4.0);
+
+    let removed = apply_changes(&updated, &[Change::RemoveLayer { device: all(), name: "Bass boost".to_string() }]).unwrap();
+    assert_eq!(removed.get("all").unwrap().selected_layer, 0);
+    assert!(apply_changes(&removed, &[Change::RemoveLayer { device: all(), name: crate::filters::DEFAULT_LAYER.to_string() }]).is_err());
+    assert!(apply_changes(&updated, &[Change::AddLayer { device: all(), name: "Bass boost".to_string() }]).is_err());
+}
@@ -1,8 +1,13 @@
 use std::env;
+use std::path::Path;
 
 use crate::errors::AppError;
 
-pub fn get_equalizer_apo_config_dir() -> Result<String, AppError> {
-    let dir = env::current_dir()?;
+/// The fixture's config directory, or the working directory.
+pub fn get_equalizer_apo_config_dir(fixture_dir: Option<&Path>) -> Result<String, AppError> {
+    let dir = match fixture_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => env::current_dir()?,
+    };
     Ok(dir.to_string_lossy().to_string())
 }
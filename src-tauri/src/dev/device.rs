@@ -1,20 +1,96 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::backend::{self, DeviceInfo, Direction, MixFormat, SPEAKER_POSITIONS};
 use crate::errors::AppError;
 
-#[derive(Clone, Serialize)]
-pub struct DeviceInfo {
+/// A device as described in the fixture.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DevDevice {
     pub guid: String,
     pub name: String,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub is_default: bool,
+    #[serde(default = "default_apo_installed")]
     pub apo_installed: bool,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default = "default_bit_depth")]
+    pub bit_depth: u16,
+    #[serde(default = "default_channels")]
+    pub channels: Vec<String>,
 }
 
-impl DeviceInfo {
-    pub fn enumerate() -> Result<Vec<DeviceInfo>, AppError> {
-        Ok(vec![DeviceInfo{
-            guid: format!("xxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"),
-            name: format!("Dummy Device"),
-            apo_installed: true,
-        }])
+impl DevDevice {
+    pub fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            guid: self.guid.to_lowercase(),
+            name: self.name.clone(),
+            apo_installed: self.apo_installed,
+            is_default: self.is_default,
+            direction: self.direction,
+            format: Some(MixFormat {
+                sample_rate: self.sample_rate,
+                bit_depth: self.bit_depth,
+                channels: self.channels.len() as u16,
+                channel_mask: backend::channel_mask(&self.channels),
+            }),
+        }
     }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.guid.trim().is_empty() || self.name.trim().is_empty() {
+            return Err(AppError::invalid_config("Fixture devices need a guid and a name".to_string()));
+        }
+        if self.sample_rate == 0 {
+            return Err(AppError::invalid_config(format!("Invalid sample rate for {}", self.name)));
+        }
+        if ![8, 16, 24, 32].contains(&self.bit_depth) {
+            return Err(AppError::invalid_config(format!("Invalid bit depth {} for {}", self.bit_depth, self.name)));
+        }
+        if self.channels.is_empty() {
+            return Err(AppError::invalid_config(format!("{} has no channels", self.name)));
+        }
+        if let Some(c) = self.channels.iter().find(|c| !SPEAKER_POSITIONS.contains(&c.as_str())) {
+            return Err(AppError::invalid_config(format!("Unknown speaker position {} for {}", c, self.name)));
+        }
+        Ok(())
+    }
+}
+
+/// What the development backend shows without a fixture.
+pub fn dummy() -> DevDevice {
+    DevDevice {
+        guid: "{xxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}".to_string(),
+        name: "Dummy Device".to_string(),
+        direction: Direction::Render,
+        is_default: true,
+        apo_installed: true,
+        sample_rate: default_sample_rate(),
+        bit_depth: default_bit_depth(),
+        channels: default_channels(),
+    }
+}
+
+pub fn enumerate(devices: &[DevDevice]) -> Result<Vec<DeviceInfo>, AppError> {
+    Ok(devices.iter().map(DevDevice::info).collect())
+}
+
+fn default_apo_installed() -> bool {
+    true
+}
+
+fn default_sample_rate() -> u32 {
+    48000
+}
+
+/// Windows mixes in 32 bit float.
+fn default_bit_depth() -> u16 {
+    32
+}
+
+fn default_channels() -> Vec<String> {
+    vec!["FL".to_string(), "FR".to_string()]
 }
+
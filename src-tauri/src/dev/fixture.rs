@@ -0,0 +1,190 @@
+//! The JSON fixture the development backend is built from.
+//!
+//! ```json
+//! {
+//!     "config_dir": "apo-config",
+//!     "devices": [
+//!         { "guid": "{...-0001}", "name": "Speakers", "is_default": true, "sample_rate": 48000, "bit_depth": 24, "channels": ["FL", "FR"] },
+//!         { "guid": "{...-0002}", "name": "HDMI", "apo_installed": false },
+//!         { "guid": "{...-0101}", "name": "Microphone", "direction": "capture", "is_default": true, "channels": ["FC"] }
+//!     ],
+//!     "hotplug": [
+//!         { "after_ms": 5000, "action": "add", "device": { "guid": "{...-0003}", "name": "USB DAC" } },
+//!         { "after_ms": 8000, "action": "set_default", "guid": "{...-0003}" },
+//!         { "after_ms": 12000, "action": "remove", "guid": "{...-0003}" }
+//!     ]
+//! }
+//! ```
+//!
+//! Devices are outputs unless their `direction` says `capture`; each direction has its own
+//! default. A relative `config_dir` is resolved against the fixture's directory. The hotplug events are
+//! played once, timed from when the device watcher starts.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::backend::Direction;
+use crate::errors::{AppError, ErrorType};
+
+use super::device::{self, DevDevice};
+
+/// Path to a fixture file, or the fixture's JSON itself.
+pub const FIXTURE_ENV: &str = "EQPLUS_DEV_FIXTURE";
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Fixture {
+    #[serde(default)]
+    pub config_dir: Option<PathBuf>,
+    pub devices: Vec<DevDevice>,
+    #[serde(default)]
+    pub hotplug: Vec<HotplugEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct HotplugEvent {
+    pub after_ms: u64,
+    #[serde(flatten)]
+    pub action: Hotplug,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Hotplug {
+    Add { device: DevDevice },
+    Remove { guid: String },
+    SetDefault { guid: String },
+    SetApoInstalled { guid: String, installed: bool },
+}
+
+impl Default for Fixture {
+    fn default() -> Fixture {
+        Fixture { config_dir: None, devices: vec![device::dummy()], hotplug: vec![] }
+    }
+}
+
+impl Fixture {
+    /// The fixture named by `EQPLUS_DEV_FIXTURE`, or the single dummy device.
+    pub fn from_env() -> Result<Fixture, AppError> {
+        match env::var(FIXTURE_ENV).ok().filter(|v| !v.trim().is_empty()) {
+            None => Ok(Fixture::default()),
+            Some(json) if json.trim_start().starts_with('{') => Fixture::parse(&json),
+            Some(path) => Fixture::load(Path::new(&path)),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Fixture, AppError> {
+        let contents = fs::read_to_string(path).map_err(|e| AppError {
+            err_type: ErrorType::GenericIoError,
+            message: format!("Could not read device fixture {}: {}", path.display(), e)
+        })?;
+        let mut fixture = Fixture::parse(&contents)?;
+        if let (Some(dir), Some(base)) = (&fixture.config_dir, path.parent()) {
+            fixture.config_dir = Some(base.join(dir));
+        }
+        Ok(fixture)
+    }
+
+    pub fn parse(json: &str) -> Result<Fixture, AppError> {
+        let fixture: Fixture = serde_json::from_str(json).map_err(|e| AppError::invalid_config(format!("Invalid device fixture: {}", e)))?;
+        validate_devices(&fixture.devices)?;
+        Ok(fixture)
+    }
+}
+
+pub fn validate_devices(devices: &[DevDevice]) -> Result<(), AppError> {
+    let mut guids = HashSet::new();
+    for device in devices {
+        device.validate()?;
+        if !guids.insert(device.guid.to_lowercase()) {
+            return Err(AppError::invalid_config(format!("Duplicate device guid {}", device.guid)));
+        }
+    }
+    for direction in Direction::ALL {
+        if devices.iter().filter(|d| d.direction == direction && d.is_default).count() > 1 {
+            return Err(AppError::invalid_config(format!("More than one {} device is marked as default", direction.name())));
+        }
+    }
+    Ok(())
+}
+
+/// Applies one hotplug event to the device list.
+pub fn apply(devices: &mut Vec<DevDevice>, action: &Hotplug) -> Result<(), AppError> {
+    let position = |devices: &[DevDevice], guid: &str| {
+        devices
+            .iter()
+            .position(|d| d.guid.eq_ignore_ascii_case(guid))
+            .ok_or_else(|| AppError::bad_arguments(format!("Could not find device with guid {}", guid)))
+    };
+    let mut updated = devices.clone();
+    match action {
+        Hotplug::Add { device } => {
+            if device.is_default {
+                updated.iter_mut().filter(|d| d.direction == device.direction).for_each(|d| d.is_default = false);
+            }
+            updated.push(device.clone());
+        },
+        Hotplug::Remove { guid } => {
+            updated.remove(position(&updated, guid)?);
+        },
+        Hotplug::SetDefault { guid } => {
+            let i = position(&updated, guid)?;
+            let direction = updated[i].direction;
+            updated
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, d)| d.direction == direction)
+                .for_each(|(j, d)| d.is_default = i == j);
+        },
+        Hotplug::SetApoInstalled { guid, installed } => {
+            let i = position(&updated, guid)?;
+            updated[i].apo_installed = *installed;
+        },
+    }
+    validate_devices(&updated)?;
+    *devices = updated;
+    Ok(())
+}
+
+
+#[test]
+fn test_fixture() {
+    let fixture = Fixture::parse(r#"{
+        "config_dir": "apo",
+        "devices": [
+            { "guid": "{0001}", "name": "Speakers", "is_default": true },
+            { "guid": "{0002}", "name": "Surround", "apo_installed": false, "sample_rate": 96000, "channels": ["FL", "FR", "FC", "LFE", "BL", "BR"] },
+            { "guid": "{0101}", "name": "Microphone", "direction": "capture", "is_default": true, "channels": ["FC"] }
+        ],
+        "hotplug": [
+            { "after_ms": 100, "action": "add", "device": { "guid": "{0003}", "name": "USB DAC", "is_default": true } },
+            { "after_ms": 200, "action": "remove", "guid": "{0001}" }
+        ]
+    }"#).unwrap();
+    assert_eq!(fixture.devices[0].channels, vec!["FL", "FR"]);
+    assert!(fixture.devices[0].apo_installed);
+    assert_eq!(fixture.devices[1].sample_rate, 96000);
+    assert_eq!(fixture.devices[1].info().format.unwrap().channel_mask, 0x3f);
+    assert_eq!(fixture.devices[2].info().format.unwrap().channels, 1);
+    assert_eq!(fixture.devices[2].direction, Direction::Capture);
+    assert_eq!(fixture.hotplug[1].action, Hotplug::Remove { guid: "{0001}".to_string() });
+
+    let mut devices = fixture.devices.clone();
+    for event in &fixture.hotplug {
+        apply(&mut devices, &event.action).unwrap();
+    }
+    let defaults: Vec<_> = devices.iter().map(|d| (d.guid.as_str(), d.is_default)).collect();
+    assert_eq!(defaults, vec![("{0002}", false), ("{0101}", true), ("{0003}", true)]);
+    assert!(apply(&mut devices, &Hotplug::SetDefault { guid: "{0001}".to_string() }).is_err());
+    let duplicate = Hotplug::Add { device: devices[0].clone() };
+    assert!(apply(&mut devices, &duplicate).is_err());
+    assert_eq!(devices.len(), 3);
+
+    assert!(Fixture::parse(r#"{ "devices": [{ "guid": "{0001}", "name": "Speakers", "channels": ["XX"] }] }"#).is_err());
+    assert!(Fixture::parse(r#"{ "devices": [{ "guid": "{0001}", "name": "Speakers", "bit_depth": 20 }] }"#).is_err());
+    assert!(Fixture::parse(r#"{ "devices": [{ "guid": "{1}", "name": "A", "is_default": true }, { "guid": "{2}", "name": "B", "is_default": true }] }"#).is_err());
+    assert_eq!(Fixture::default().devices.len(), 1);
+}
@@ -1,2 +1,156 @@
+//! Development backend: devices and config dir come from a JSON fixture (see `fixture`), so the
+//! frontend and the commands can be worked on without EqualizerAPO. Without a fixture it shows a
+//! single dummy device and uses the working directory as config dir.
+
 pub mod device;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod fixture;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::backend::{self, AudioBackend, DeviceChangeCallback, DeviceInfo, Watcher};
+use crate::errors::AppError;
+use crate::registry::apo::FxSlot;
+
+use device::DevDevice;
+use fixture::{Fixture, Hotplug, HotplugEvent};
+
+pub struct DevBackend {
+    config_dir: Option<PathBuf>,
+    devices: Mutex<Vec<DevDevice>>,
+    hotplug: Mutex<Vec<HotplugEvent>>,
+    listeners: Mutex<Vec<DeviceChangeCallback>>,
+}
+
+impl DevBackend {
+    /// Uses the fixture named by `EQPLUS_DEV_FIXTURE`.
+    pub fn new() -> Result<DevBackend, AppError> {
+        Ok(DevBackend::with_fixture(Fixture::from_env()?))
+    }
+
+    pub fn with_fixture(fixture: Fixture) -> DevBackend {
+        DevBackend {
+            config_dir: fixture.config_dir,
+            devices: Mutex::new(fixture.devices),
+            hotplug: Mutex::new(fixture.hotplug),
+            listeners: Mutex::new(vec![]),
+        }
+    }
+
+    /// Plugs, unplugs or changes a device as if the audio stack had reported it, and sends the
+    /// resulting events to the watchers.
+    pub fn simulate(&self, action: &Hotplug) -> Result<(), AppError> {
+        let (before, after) = {
+            let mut devices = self.devices.lock().unwrap();
+            let before = device::enumerate(&devices)?;
+            fixture::apply(&mut devices, action)?;
+            (before, device::enumerate(&devices)?)
+        };
+        info!("simulated device change: {:?}", action);
+        let events = backend::device_events(&before, &after);
+        if !events.is_empty() {
+            for listener in self.listeners.lock().unwrap().iter() {
+                listener(&events);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AudioBackend for DevBackend {
+    fn name(&self) -> &'static str {
+        "dev"
+    }
+
+    fn config_dir(&self) -> Result<String, AppError> {
+        config::get_equalizer_apo_config_dir(self.config_dir.as_deref())
+    }
+
+    fn enumerate(&self) -> Result<Vec<DeviceInfo>, AppError> {
+        device::enumerate(&self.devices.lock().unwrap())
+    }
+
+    /// Where the Configurator puts APO by default.
+    fn apo_slots(&self, device: &DeviceInfo) -> Result<Option<Vec<FxSlot>>, AppError> {
+        Ok(Some(if device.apo_installed { vec![FxSlot::PreMix, FxSlot::PostMix] } else { vec![] }))
+    }
+
+    /// Changes only ever come from `simulate`, so there is nothing to poll. The fixture's hotplug
+    /// events are played on the first watch.
+    fn watch(self: Arc<Self>, on_change: DeviceChangeCallback) -> Result<Watcher, AppError> {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listening = stopped.clone();
+        self.listeners.lock().unwrap().push(Box::new(move |events| {
+            if !listening.load(Ordering::SeqCst) {
+                on_change(events);
+            }
+        }));
+        let watcher = Watcher::new({
+            let stopped = stopped.clone();
+            move || stopped.store(true, Ordering::SeqCst)
+        });
+        let events = std::mem::take(&mut *self.hotplug.lock().unwrap());
+        if events.is_empty() {
+            return Ok(watcher);
+        }
+        thread::Builder::new()
+            .name("dev-hotplug".to_string())
+            .spawn(move || {
+                let mut elapsed = 0;
+                for event in events {
+                    thread::sleep(Duration::from_millis(event.after_ms.saturating_sub(elapsed)));
+                    elapsed = elapsed.max(event.after_ms);
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if let Err(e) = self.simulate(&event.action) {
+                        warn!("Could not simulate {:?}: {}", event.action, e);
+                    }
+                }
+            })?;
+        Ok(watcher)
+    }
+}
+
+#[test]
+fn test_dev_backend_hotplug() {
+    use std::sync::mpsc;
+    use crate::backend::{DeviceEvent, Direction};
+
+    let fixture = Fixture::parse(r#"{
+        "config_dir": "/tmp/apo",
+        "devices": [{ "guid": "{0001}", "name": "Speakers", "is_default": true }],
+        "hotplug": [{ "after_ms": 10, "action": "add", "device": { "guid": "{0002}", "name": "USB DAC" } }]
+    }"#).unwrap();
+    let backend = Arc::new(DevBackend::with_fixture(fixture));
+    assert_eq!(backend.config_dir().unwrap(), "/tmp/apo");
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let watcher = backend.clone().watch(Box::new(move |events| tx.lock().unwrap().send(events.to_vec()).unwrap())).unwrap();
+    let events = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    let devices = backend.enumerate().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(events, vec![DeviceEvent::Added { device: devices[1].clone() }]);
+
+    backend.simulate(&Hotplug::SetDefault { guid: "{0002}".to_string() }).unwrap();
+    let events = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    let devices = backend.enumerate().unwrap();
+    assert_eq!(events, vec![DeviceEvent::DefaultChanged { direction: Direction::Render, device: Some(devices[1].clone()) }]);
+
+    backend.simulate(&Hotplug::SetApoInstalled { guid: "{0002}".to_string(), installed: false }).unwrap();
+    let events = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(&events[..], [DeviceEvent::StateChanged { device }] if !device.apo_installed));
+    assert!(backend.simulate(&Hotplug::Remove { guid: "{0003}".to_string() }).is_err());
+    assert!(rx.try_recv().is_err());
+
+    drop(watcher);
+    backend.simulate(&Hotplug::Remove { guid: "{0002}".to_string() }).unwrap();
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,148 @@
+//! Why EqualizerAPO might not be doing anything for a device, and what to do about it.
+//!
+//! A device is fine when APO sits in one of its effect slots that runs the filters eq+ writes, and
+//! eq+ can write to the config directory APO actually reads. Every check that fails becomes an
+//! issue with a hint the user can act on.
+
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::backend::{DeviceInfo, Direction};
+use crate::errors::AppError;
+use crate::registry::apo::FxSlot;
+
+const WRITE_TEST_FILE: &str = ".eqplus-write-test";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    NotInstalled,
+    PreMixOnly,
+    ConfigDirNotWritable,
+    ConfigPathUnreadable,
+    ConfigPathMismatch,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub message: String,
+    /// What the user can do about it.
+    pub hint: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceDiagnosis {
+    pub device: DeviceInfo,
+    /// Slots hosting EqualizerAPO; `None` where the backend has no effect slots.
+    pub fx_slots: Option<Vec<FxSlot>>,
+    /// The directory eq+ writes to.
+    pub config_dir: String,
+    pub config_dir_writable: bool,
+    /// The directory APO's registry entry points at right now, if it could be read.
+    pub apo_config_dir: Option<String>,
+    pub issues: Vec<Issue>,
+}
+
+/// Runs every check for `device`. `apo_config_dir` is a fresh read of APO's config path, which
+/// can differ from the `config_dir` eq+ picked up on startup after APO was reinstalled.
+pub fn diagnose(device: DeviceInfo, fx_slots: Option<Vec<FxSlot>>, config_dir: &str, apo_config_dir: Result<String, AppError>) -> DeviceDiagnosis {
+    let mut issues = vec![];
+    match &fx_slots {
+        Some(slots) if slots.is_empty() => issues.push(Issue {
+            kind: IssueKind::NotInstalled,
+            message: format!("EqualizerAPO is not installed on {}", device.name),
+            hint: "Run EqualizerAPO's Configurator, select the device and restart Windows.".to_string(),
+        }),
+        // output filters run in APO's post-mix stage, which pre-mix slots never run
+        Some(slots) if device.direction == Direction::Render && !slots.iter().any(FxSlot::is_post_mix) => issues.push(Issue {
+            kind: IssueKind::PreMixOnly,
+            message: format!("EqualizerAPO only runs before the mix on {}, so its filters are not applied", device.name),
+            hint: "In the Configurator, open the device's troubleshooting options and install APO as a post-mix effect (GFX or EFX).".to_string(),
+        }),
+        _ => {},
+    }
+
+    let config_dir_writable = dir_writable(Path::new(config_dir));
+    if !config_dir_writable {
+        issues.push(Issue {
+            kind: IssueKind::ConfigDirNotWritable,
+            message: format!("eq+ cannot write to {}", config_dir),
+            hint: "Give your user write access to the folder, or run eq+ as administrator once.".to_string(),
+        });
+    }
+
+    let apo_config_dir = match apo_config_dir {
+        Ok(dir) => {
+            if !same_dir(&dir, config_dir) {
+                issues.push(Issue {
+                    kind: IssueKind::ConfigPathMismatch,
+                    message: format!("EqualizerAPO reads its config from {}, but eq+ writes to {}", dir, config_dir),
+                    hint: "Restart eq+ so it picks up EqualizerAPO's current config directory.".to_string(),
+                });
+            }
+            Some(dir)
+        },
+        Err(e) => {
+            issues.push(Issue {
+                kind: IssueKind::ConfigPathUnreadable,
+                message: format!("Could not read EqualizerAPO's config path: {}", e.message),
+                hint: "Reinstall EqualizerAPO to restore its registry entries.".to_string(),
+            });
+            None
+        },
+    };
+
+    DeviceDiagnosis { device, fx_slots, config_dir: config_dir.to_string(), config_dir_writable, apo_config_dir, issues }
+}
+
+/// Tries to create a file, since permissions alone do not tell about UAC virtualization or
+/// read-only mounts.
+fn dir_writable(dir: &Path) -> bool {
+    let probe = dir.join(WRITE_TEST_FILE);
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn same_dir(a: &str, b: &str) -> bool {
+    let normalize = |path: &str| {
+        let path = path.replace('/', "\\").trim_end_matches('\\').to_string();
+        // Windows paths are case insensitive
+        if cfg!(windows) { path.to_lowercase() } else { path }
+    };
+    normalize(a) == normalize(b)
+}
+
+#[test]
+fn test_diagnose() {
+    use crate::errors::ErrorType;
+
+    let dir = crate::storage::TestDir::new("diagnose");
+    let config_dir = dir.to_string_lossy().to_string();
+    let speakers = DeviceInfo { guid: "{0001}".to_string(), name: "Speakers".to_string(), apo_installed: true, is_default: true, direction: Direction::Render, format: None };
+
+    let healthy = diagnose(speakers.clone(), Some(vec![FxSlot::PreMix, FxSlot::PostMix]), &config_dir, Ok(format!("{}/", config_dir)));
+    assert!(healthy.issues.is_empty());
+    assert!(healthy.config_dir_writable);
+    assert!(!dir.join(WRITE_TEST_FILE).exists());
+
+    let kinds = |diagnosis: DeviceDiagnosis| diagnosis.issues.iter().map(|i| i.kind).collect::<Vec<_>>();
+    assert_eq!(kinds(diagnose(speakers.clone(), Some(vec![]), &config_dir, Ok(config_dir.clone()))), vec![IssueKind::NotInstalled]);
+    assert!(kinds(diagnose(speakers.clone(), Some(vec![FxSlot::Efx]), &config_dir, Ok(config_dir.clone()))).is_empty());
+    assert_eq!(kinds(diagnose(speakers.clone(), Some(vec![FxSlot::Sfx, FxSlot::Mfx]), &config_dir, Ok(config_dir.clone()))), vec![IssueKind::PreMixOnly]);
+    assert!(kinds(diagnose(speakers.clone(), None, &config_dir, Ok(config_dir.clone()))).is_empty());
+
+    let missing = dir.join("missing").to_string_lossy().to_string();
+    let unreadable = Err(AppError { err_type: ErrorType::RegistryError, message: "Error reading registry value".to_string() });
+    let broken = diagnose(speakers, Some(vec![FxSlot::PostMix]), &missing, unreadable);
+    assert!(!broken.config_dir_writable);
+    assert_eq!(broken.apo_config_dir, None);
+    assert_eq!(kinds(broken), vec![IssueKind::ConfigDirNotWritable, IssueKind::ConfigPathUnreadable]);
+
+    let moved = diagnose(healthy.device, healthy.fx_slots, &config_dir, Ok(missing));
+    assert_eq!(kinds(moved), vec![IssueKind::ConfigPathMismatch]);
+}
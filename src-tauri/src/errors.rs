@@ -18,6 +18,16 @@ pub struct AppError {
     pub message: String
 }
 
+impl AppError {
+    pub fn invalid_config(message: String) -> AppError {
+        AppError { err_type: ErrorType::InvalidConfig, message }
+    }
+
+    pub fn bad_arguments(message: String) -> AppError {
+        AppError { err_type: ErrorType::BadArguments, message }
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "[{:?}]: {}", self.err_type, self.message)
@@ -32,3 +42,8 @@ impl From<io::Error> for AppError {
         }
     }
 }
+
+/// No section of the mapping goes by `name`.
+pub fn no_such_section(name: &str) -> AppError {
+    AppError::bad_arguments(format!("Could not find device with name {}", name))
+}
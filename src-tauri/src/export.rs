@@ -0,0 +1,292 @@
+//! Exporters for EQ applications on other platforms and for offline tooling.
+//!
+//! All of them take the same flattened `EqState` that is written to eqplus.txt. Apart from
+//! JamesDSP, which only understands a GraphicEQ curve, the filters are carried over one to one.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::autoeq;
+use crate::errors::{AppError, ErrorType};
+use crate::filters::{trim_float, EqState, FilterParams, FilterType};
+use crate::response;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTarget {
+    PipeWire,
+    EasyEffects,
+    CamillaDsp,
+    JamesDsp,
+    Ffmpeg,
+    Sox,
+}
+
+pub fn export(eq: &EqState, target: ExportTarget) -> Result<String, AppError> {
+    if target != ExportTarget::JamesDsp && !eq.graphic_eq.is_empty() {
+        return Err(AppError {
+            err_type: ErrorType::BadArguments,
+            message: format!("An EQ containing a GraphicEQ curve can not be exported to {:?}", target)
+        });
+    }
+    Ok(match target {
+        ExportTarget::PipeWire => pipewire(eq),
+        ExportTarget::EasyEffects => easy_effects(eq),
+        ExportTarget::CamillaDsp => camilla_dsp(eq),
+        ExportTarget::JamesDsp => james_dsp(eq),
+        ExportTarget::Ffmpeg => ffmpeg(eq),
+        ExportTarget::Sox => sox(eq),
+    })
+}
+
+/// A filter-chain module with a single builtin `param_eq` node, ready for `pipewire.conf.d`.
+/// The preamp is a high shelf at 0 Hz, which is a plain gain stage.
+pub fn pipewire(eq: &EqState) -> String {
+    let mut filters = vec![format!("{{ type = bq_highshelf freq = 0 gain = {} q = 1.0 }}", number(eq.preamp))];
+    for filter in &eq.filters {
+        filters.push(format!(
+            "{{ type = {} freq = {} gain = {} q = {} }}",
+            pipewire_type(filter.filter_type), number(filter.frequency), number(filter.gain), number(filter.q)
+        ));
+    }
+    let filters: Vec<String> = filters.iter().map(|f| format!("                                {}", f)).collect();
+    format!(
+r#"context.modules = [
+    {{ name = libpipewire-module-filter-chain
+        args = {{
+            node.description = "eq+ equalizer"
+            media.name       = "eq+ equalizer"
+            filter.graph = {{
+                nodes = [
+                    {{
+                        type  = builtin
+                        name  = eq
+                        label = param_eq
+                        config = {{
+                            filters = [
+{}
+                            ]
+                        }}
+                    }}
+                ]
+            }}
+            audio.channels = 2
+            audio.position = [ FL FR ]
+            capture.props = {{
+                node.name   = "effect_input.eqplus"
+                media.class = Audio/Sink
+            }}
+            playback.props = {{
+                node.name    = "effect_output.eqplus"
+                node.passive = true
+            }}
+        }}
+    }}
+]
+"#, filters.join("\n"))
+}
+
+pub fn pipewire_type(filter_type: FilterType) -> &'static str {
+    match filter_type {
+        FilterType::AllPass => "bq_allpass",
+        FilterType::BandPass => "bq_bandpass",
+        FilterType::HighPass => "bq_highpass",
+        FilterType::HighShelf => "bq_highshelf",
+        FilterType::LowPass => "bq_lowpass",
+        FilterType::LowShelf => "bq_lowshelf",
+        FilterType::Notch => "bq_notch",
+        FilterType::Peaking => "bq_peaking",
+    }
+}
+
+/// An EasyEffects output preset with one equalizer in APO compatible mode, same bands on both
+/// channels.
+pub fn easy_effects(eq: &EqState) -> String {
+    let mut bands = Map::new();
+    for (i, filter) in eq.filters.iter().enumerate() {
+        bands.insert(format!("band{}", i), json!({
+            "frequency": filter.frequency,
+            "gain": filter.gain,
+            "mode": "APO (DR)",
+            "mute": false,
+            "q": filter.q,
+            "slope": "x1",
+            "solo": false,
+            "type": easy_effects_type(filter.filter_type),
+        }));
+    }
+    let preset = json!({
+        "output": {
+            "blocklist": [],
+            "equalizer#0": {
+                "balance": 0.0,
+                "bypass": false,
+                "input-gain": eq.preamp,
+                "left": Value::Object(bands.clone()),
+                "mode": "IIR",
+                "num-bands": eq.filters.len(),
+                "output-gain": 0.0,
+                "pitch-left": 0.0,
+                "pitch-right": 0.0,
+                "right": Value::Object(bands),
+                "split-channels": false,
+            },
+            "plugins_order": ["equalizer#0"],
+        }
+    });
+    serde_json::to_string_pretty(&preset).unwrap()
+}
+
+fn easy_effects_type(filter_type: FilterType) -> &'static str {
+    match filter_type {
+        FilterType::AllPass => "Allpass",
+        FilterType::BandPass => "Bandpass",
+        FilterType::HighPass => "Hi-pass",
+        FilterType::HighShelf => "Hi-shelf",
+        FilterType::LowPass => "Lo-pass",
+        FilterType::LowShelf => "Lo-shelf",
+        FilterType::Notch => "Notch",
+        FilterType::Peaking => "Bell",
+    }
+}
+
+/// The `filters` and `pipeline` sections of a CamillaDSP (v2) config for a stereo device.
+pub fn camilla_dsp(eq: &EqState) -> String {
+    let mut lines = vec![
+        "filters:".to_string(),
+        "  preamp:".to_string(),
+        "    type: Gain".to_string(),
+        "    parameters:".to_string(),
+        format!("      gain: {}", number(eq.preamp)),
+    ];
+    let mut names = vec!["preamp".to_string()];
+    for (i, filter) in eq.filters.iter().enumerate() {
+        let name = format!("filter_{}", i + 1);
+        lines.push(format!("  {}:", name));
+        lines.push("    type: Biquad".to_string());
+        lines.push("    parameters:".to_string());
+        lines.push(format!("      type: {}", camilla_type(filter.filter_type)));
+        lines.push(format!("      freq: {}", number(filter.frequency)));
+        if has_gain(filter) {
+            lines.push(format!("      gain: {}", number(filter.gain)));
+        }
+        lines.push(format!("      q: {}", number(filter.q)));
+        names.push(name);
+    }
+    lines.push("pipeline:".to_string());
+    lines.push("  - type: Filter".to_string());
+    lines.push("    channels: [0, 1]".to_string());
+    lines.push("    names:".to_string());
+    lines.extend(names.iter().map(|n| format!("      - {}", n)));
+    lines.join("\n") + "\n"
+}
+
+fn camilla_type(filter_type: FilterType) -> &'static str {
+    match filter_type {
+        FilterType::AllPass => "Allpass",
+        FilterType::BandPass => "Bandpass",
+        FilterType::HighPass => "Highpass",
+        FilterType::HighShelf => "Highshelf",
+        FilterType::LowPass => "Lowpass",
+        FilterType::LowShelf => "Lowshelf",
+        FilterType::Notch => "Notch",
+        FilterType::Peaking => "Peaking",
+    }
+}
+
+/// The `GraphicEQ: ...` string JamesDSP (and Wavelet) accept, sampled from the full response.
+pub fn james_dsp(eq: &EqState) -> String {
+    autoeq::export_graphic(eq, response::DEFAULT_SAMPLE_RATE).trim_end().to_string()
+}
+
+/// An ffmpeg `-af` filter graph, e.g. `volume=-6.2dB,equalizer=f=1000:t=q:w=1.41:g=-3`.
+pub fn ffmpeg(eq: &EqState) -> String {
+    let mut filters = vec![format!("volume={}dB", number(eq.preamp))];
+    for filter in &eq.filters {
+        let name = match filter.filter_type {
+            FilterType::AllPass => "allpass",
+            FilterType::BandPass => "bandpass",
+            FilterType::HighPass => "highpass",
+            FilterType::HighShelf => "highshelf",
+            FilterType::LowPass => "lowpass",
+            FilterType::LowShelf => "lowshelf",
+            FilterType::Notch => "bandreject",
+            FilterType::Peaking => "equalizer",
+        };
+        let mut args = format!("{}=f={}:t=q:w={}", name, number(filter.frequency), number(filter.q));
+        if has_gain(filter) {
+            args.push_str(&format!(":g={}", number(filter.gain)));
+        }
+        filters.push(args);
+    }
+    filters.join(",")
+}
+
+/// SoX effect arguments, e.g. `gain -6.2 equalizer 1000 1.41q -3`.
+pub fn sox(eq: &EqState) -> String {
+    let mut effects = vec![format!("gain {}", number(eq.preamp))];
+    for filter in &eq.filters {
+        let (fc, gain, width) = (number(filter.frequency), number(filter.gain), format!("{}q", number(filter.q)));
+        effects.push(match filter.filter_type {
+            FilterType::AllPass => format!("allpass {} {}", fc, width),
+            FilterType::BandPass => format!("bandpass {} {}", fc, width),
+            FilterType::HighPass => format!("highpass -2 {} {}", fc, width),
+            FilterType::HighShelf => format!("treble {} {} {}", gain, fc, width),
+            FilterType::LowPass => format!("lowpass -2 {} {}", fc, width),
+            FilterType::LowShelf => format!("bass {} {} {}", gain, fc, width),
+            FilterType::Notch => format!("bandreject {} {}", fc, width),
+            FilterType::Peaking => format!("equalizer {} {} {}", fc, width, gain),
+        });
+    }
+    effects.join(" ")
+}
+
+fn has_gain(filter: &FilterParams) -> bool {
+    matches!(filter.filter_type, FilterType::Peaking | FilterType::LowShelf | FilterType::HighShelf)
+}
+
+fn number(value: f64) -> String {
+    trim_float(value, 3)
+}
+
+#[test]
+fn test_exporters() {
+    let eq = EqState {
+        preamp: -6.2,
+        filters: vec![
+            FilterParams { id: "1".to_string(), frequency: 105.0, gain: 5.5, q: 0.7, filter_type: FilterType::LowShelf },
+            FilterParams { id: "2".to_string(), frequency: 1000.0, gain: -3.0, q: 1.41, filter_type: FilterType::Peaking },
+            FilterParams { id: "3".to_string(), frequency: 20.0, gain: 0.0, q: 0.707, filter_type: FilterType::HighPass },
+        ],
+        graphic_eq: vec![],
+    };
+
+    let pw = export(&eq, ExportTarget::PipeWire).unwrap();
+    assert!(pw.contains("{ type = bq_highshelf freq = 0 gain = -6.2 q = 1.0 }"));
+    assert!(pw.contains("{ type = bq_lowshelf freq = 105 gain = 5.5 q = 0.7 }"));
+
+    let ee: Value = serde_json::from_str(&export(&eq, ExportTarget::EasyEffects).unwrap()).unwrap();
+    let equalizer = &ee["output"]["equalizer#0"];
+    assert_eq!(equalizer["input-gain"], -6.2);
+    assert_eq!(equalizer["num-bands"], 3);
+    assert_eq!(equalizer["right"]["band1"]["type"], "Bell");
+
+    let camilla = export(&eq, ExportTarget::CamillaDsp).unwrap();
+    assert!(camilla.contains("  filter_3:\n    type: Biquad\n    parameters:\n      type: Highpass\n      freq: 20\n      q: 0.707\n"));
+    assert!(camilla.ends_with("      - preamp\n      - filter_1\n      - filter_2\n      - filter_3\n"));
+
+    assert_eq!(
+        export(&eq, ExportTarget::Ffmpeg).unwrap(),
+        "volume=-6.2dB,lowshelf=f=105:t=q:w=0.7:g=5.5,equalizer=f=1000:t=q:w=1.41:g=-3,highpass=f=20:t=q:w=0.707"
+    );
+    assert_eq!(
+        export(&eq, ExportTarget::Sox).unwrap(),
+        "gain -6.2 bass 5.5 105 0.7q equalizer 1000 1.41q -3 highpass -2 20 0.707q"
+    );
+
+    let james = export(&eq, ExportTarget::JamesDsp).unwrap();
+    assert!(james.starts_with("GraphicEQ: 20 ") && !james.ends_with('\n'));
+    let curve = EqState { graphic_eq: crate::filters::process_graphic_eq_line(&james).unwrap(), ..EqState::default() };
+    assert!(export(&curve, ExportTarget::Ffmpeg).is_err());
+    assert!(export(&curve, ExportTarget::JamesDsp).is_ok());
+}
@@ -3,9 +3,11 @@ use std::collections::HashMap;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::backend::Direction;
 use crate::errors::{AppError, ErrorType};
+use crate::selector::DeviceSelector;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum FilterType {
     AllPass,
@@ -18,7 +20,7 @@ pub enum FilterType {
     Peaking,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FilterParams {
     pub id: String,
     pub frequency: f64,
@@ -50,10 +52,19 @@ impl FilterParams {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+/// One point of a `GraphicEQ:` curve.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GraphicEqPoint {
+    pub frequency: f64,
+    pub gain: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct EqState {
     pub preamp: f64,
     pub filters: Vec<FilterParams>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub graphic_eq: Vec<GraphicEqPoint>,
 }
 
 impl EqState {
@@ -66,7 +77,8 @@ impl EqState {
                 FilterParams { id: String::from("2"), frequency: 225.0, gain: 0.0, q: 1.0, filter_type: FilterType::Peaking },
                 FilterParams { id: String::from("3"), frequency: 1067.0, gain: 0.0, q: 1.0, filter_type: FilterType::Peaking },
                 FilterParams { id: String::from("4"), frequency: 5060.0, gain: 0.0, q: 1.0, filter_type: FilterType::Peaking }
-            ]
+            ],
+            graphic_eq: vec![],
         }
     }
     
@@ -78,11 +90,19 @@ impl EqState {
     pub fn from_lines(lines: &Vec<&str>) -> Result<EqState, AppError> {
         let mut filters: Vec<FilterParams> = vec![];
         let mut preamp = 0.0f64;
+        let mut graphic_eq: Vec<GraphicEqPoint> = vec![];
 
         for line in lines {
             if line.contains("Channel") {
                 return Err(err(String::from("Independent channel EQ is not currently supported")));
             }
+            if line.contains("GraphicEQ") {
+                if !graphic_eq.is_empty() {
+                    return Err(err(format!("Only one GraphicEQ line per EQ is supported: {}", line)));
+                }
+                graphic_eq = process_graphic_eq_line(line)?;
+                continue;
+            }
             if line.contains("Preamp") {
                 preamp = process_preamp_line(line)?;
                 continue;
@@ -94,7 +114,7 @@ impl EqState {
             }
         }
 
-        Ok(EqState { preamp, filters })
+        Ok(EqState { preamp, filters, graphic_eq })
     }
 
     pub fn to_apo(&self, disabled: bool) -> String {
@@ -108,19 +128,90 @@ impl EqState {
         } else {
             lines.push(format!("Preamp: {:.1} dB", self.preamp));
         }
-        for filter in &self.filters {
-            let line = if disabled {
-                format!("#{}", filter.to_apo_line())
+        for line in self.filter_lines() {
+            if disabled {
+                lines.push(format!("#{}", line));
             } else {
-                filter.to_apo_line()
-            };
-            lines.push(line);
+                lines.push(line);
+            }
         }
         lines.join("\n")
     }
+
+    /// The `Filter` lines, followed by the `GraphicEQ` line if there is a curve.
+    pub fn filter_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.filters.iter().map(|f| f.to_apo_line()).collect();
+        if !self.graphic_eq.is_empty() {
+            lines.push(graphic_eq_line(&self.graphic_eq));
+        }
+        lines
+    }
 }
 
-fn process_preamp_line(line: &str) -> Result<f64, AppError> {
+/// Gain of a GraphicEQ curve at a frequency; like APO, points are joined linearly over log
+/// frequency and the curve is held flat beyond its first and last point.
+pub fn graphic_eq_gain(points: &[GraphicEqPoint], frequency: f64) -> f64 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 0.0,
+    };
+    if frequency <= first.frequency {
+        return first.gain;
+    }
+    if frequency >= last.frequency {
+        return last.gain;
+    }
+    let upper = points.iter().position(|p| p.frequency >= frequency).unwrap();
+    let (lo, hi) = (points[upper - 1], points[upper]);
+    let t = (frequency.ln() - lo.frequency.ln()) / (hi.frequency.ln() - lo.frequency.ln());
+    lo.gain + (hi.gain - lo.gain) * t
+}
+
+/// Weighted sum of several curves, evaluated at every frequency any of them defines.
+pub fn combine_graphic_eq(curves: &[(&[GraphicEqPoint], f64)]) -> Vec<GraphicEqPoint> {
+    let mut frequencies: Vec<f64> = curves
+        .iter()
+        .filter(|(points, _)| !points.is_empty())
+        .flat_map(|(points, _)| points.iter().map(|p| p.frequency))
+        .collect();
+    frequencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    frequencies.dedup();
+    frequencies
+        .into_iter()
+        .map(|frequency| GraphicEqPoint {
+            frequency,
+            gain: curves
+                .iter()
+                .filter(|(points, _)| !points.is_empty())
+                .map(|(points, weight)| graphic_eq_gain(points, frequency) * weight)
+                .sum(),
+        })
+        .collect()
+}
+
+pub fn graphic_eq_line(points: &[GraphicEqPoint]) -> String {
+    let values: Vec<String> = points
+        .iter()
+        .map(|p| format!("{} {}", trim_float(p.frequency, 2), trim_float(p.gain, 1)))
+        .collect();
+    format!("GraphicEQ: {}", values.join("; "))
+}
+
+/// Formats with at most `decimals` decimals, dropping trailing zeros (`20`, `-6.3`).
+pub fn trim_float(value: f64, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, value);
+    let trimmed = if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        formatted.as_str()
+    };
+    match trimmed {
+        "-0" => "0".to_string(),
+        t => t.to_string(),
+    }
+}
+
+pub fn process_preamp_line(line: &str) -> Result<f64, AppError> {
     let re = Regex::new(r"Preamp:\s*(?P<preamp>.+) dB").unwrap();
     let caps = match re.captures(line) {
         Some(c) => c,
@@ -133,7 +224,7 @@ fn process_preamp_line(line: &str) -> Result<f64, AppError> {
     }
 }
 
-fn process_filter_line(line: &str) -> Result<FilterParams, AppError> {
+pub fn process_filter_line(line: &str) -> Result<FilterParams, AppError> {
     let mut filter = FilterParams { id: String::from(""), frequency: 1.0, gain: 0.0, q: 1.0, filter_type: FilterType::Peaking };
 
     let re = Regex::new(r"\s*Filter\s+(?P<id>\d+):\s*(?P<tokens>.+)\s*").unwrap();
@@ -220,13 +311,38 @@ fn process_filter_line(line: &str) -> Result<FilterParams, AppError> {
     Ok(filter)
 }
 
+pub fn process_graphic_eq_line(line: &str) -> Result<Vec<GraphicEqPoint>, AppError> {
+    let re = Regex::new(r"GraphicEQ:\s*(?P<points>.*)").unwrap();
+    let caps = match re.captures(line) {
+        Some(c) => c,
+        _ => return Err(err(format!("Malformed GraphicEQ line: {}", line)))
+    };
+    let mut points: Vec<GraphicEqPoint> = vec![];
+    for raw_point in caps.name("points").unwrap().as_str().split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let values: Vec<&str> = raw_point.split_whitespace().collect();
+        let point = match values.as_slice() {
+            [f, g] => match (f.parse::<f64>(), g.parse::<f64>()) {
+                (Ok(frequency), Ok(gain)) if frequency > 0.0 => GraphicEqPoint { frequency, gain },
+                _ => return Err(err(format!("Malformed GraphicEQ line (invalid values \"{}\"): {}", raw_point, line)))
+            },
+            _ => return Err(err(format!("Malformed GraphicEQ line (expected frequency and gain in \"{}\"): {}", raw_point, line)))
+        };
+        points.push(point);
+    }
+    if points.is_empty() {
+        return Err(err(format!("Malformed GraphicEQ line (no values): {}", line)));
+    }
+    points.sort_by(|a, b| a.frequency.partial_cmp(&b.frequency).unwrap());
+    Ok(points)
+}
+
 fn process_filter_type(raw_filter_type: &str) -> Result<FilterType, AppError> {
     match raw_filter_type {
         "PK" => Ok(FilterType::Peaking),
         "LP"|"LPQ" => Ok(FilterType::LowPass),
         "HP"|"HPQ" => Ok(FilterType::HighPass),
         "BP" => Ok(FilterType::BandPass),
-        "LS"|"LSC" => Ok(FilterType::BandPass),
+        "LS"|"LSC" => Ok(FilterType::LowShelf),
         "HS"|"HSC" => Ok(FilterType::HighShelf),
         "NO" => Ok(FilterType::Notch),
         "AP" => Ok(FilterType::AllPass),
@@ -234,33 +350,175 @@ fn process_filter_type(raw_filter_type: &str) -> Result<FilterType, AppError> {
     }
 }
 
+/// One named EQ in a device's stack, e.g. a headphone correction or a bass boost on top of it.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct FilterBank {
+pub struct Layer {
+    pub name: String,
     pub enabled: bool,
+    /// Level offset of the whole layer in dB, added on top of its preamp.
+    pub gain: f64,
     pub eq: EqState,
 }
 
+impl Layer {
+    pub fn new(name: &str, eq: EqState) -> Layer {
+        Layer { name: name.to_string(), enabled: true, gain: 0.0, eq }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilterBank {
+    pub enabled: bool,
+    pub layers: Vec<Layer>,
+    /// Index of the layer that filter and preamp edits go to.
+    pub selected_layer: usize,
+    /// Whether the device also gets the "all" bank applied before its own.
+    #[serde(default = "default_inherit_all")]
+    pub inherit_all: bool,
+    /// Microphone banks run in APO's capture stage, output banks after the mix.
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+fn default_inherit_all() -> bool {
+    true
+}
+
 pub type DeviceFilterMapping = HashMap<String, FilterBank>;
 
+pub const ALL_DEVICES: &str = "all";
+pub const DEFAULT_LAYER: &str = "Main";
+const LAYER_MARKER: &str = "# eq+ layer:";
+const NO_INHERIT_MARKER: &str = "# eq+ inherit: false";
+const CAPTURE_STAGE: &str = "Stage: capture";
+const RENDER_STAGE: &str = "Stage: post-mix";
+
+/// How a layer is described in eqplus.txt, as JSON behind `LAYER_MARKER`.
+#[derive(Serialize, Deserialize)]
+struct LayerHeader {
+    name: String,
+    enabled: bool,
+    gain: f64,
+    preamp: f64,
+    #[serde(default)]
+    selected: bool,
+}
+
 impl FilterBank {
     pub fn default() -> DeviceFilterMapping {
         let mut map: DeviceFilterMapping = DeviceFilterMapping::new();
         map.insert(
-            "all".to_string(),
-            FilterBank {
-                enabled: true,
-                eq: EqState::default(),
-            }
+            ALL_DEVICES.to_string(),
+            FilterBank::new(EqState::default())
         );
         map
     }
 
+    /// A bank with a single layer holding `eq`.
+    pub fn new(eq: EqState) -> FilterBank {
+        FilterBank { enabled: true, layers: vec![Layer::new(DEFAULT_LAYER, eq)], selected_layer: 0, inherit_all: true, direction: Direction::Render }
+    }
+
+    pub fn eq(&self) -> &EqState {
+        &self.layers[self.selected_layer].eq
+    }
+
+    pub fn eq_mut(&mut self) -> &mut EqState {
+        &mut self.layers[self.selected_layer].eq
+    }
+
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    /// The single chain APO ends up running: every enabled layer's filters in stack order, with
+    /// the layers' preamps and gains summed up.
+    pub fn flattened(&self) -> EqState {
+        let mut preamp = 0.0;
+        let mut filters: Vec<FilterParams> = vec![];
+        let mut curves: Vec<(&[GraphicEqPoint], f64)> = vec![];
+        for layer in self.layers.iter().filter(|l| l.enabled) {
+            preamp += layer.eq.preamp + layer.gain;
+            filters.extend(layer.eq.filters.iter().cloned());
+            curves.push((&layer.eq.graphic_eq, 1.0));
+        }
+        for (i, filter) in filters.iter_mut().enumerate() {
+            filter.id = (i + 1).to_string();
+        }
+        EqState { preamp, filters, graphic_eq: combine_graphic_eq(&curves) }
+    }
+
+    /// The flattened chain, followed by each layer's filters under a header describing it so the
+    /// layers can be recovered when the file is read back. Disabled layers are commented out.
+    pub fn to_apo(&self) -> String {
+        let mut lines: Vec<String> = vec![];
+        let comment = if self.enabled { "" } else { "#" };
+        lines.push(format!("{}Preamp: {:.1} dB", comment, self.flattened().preamp));
+        for (i, layer) in self.layers.iter().enumerate() {
+            let header = LayerHeader {
+                name: layer.name.clone(),
+                enabled: layer.enabled,
+                gain: layer.gain,
+                preamp: layer.eq.preamp,
+                selected: i == self.selected_layer,
+            };
+            lines.push(format!("{} {}", LAYER_MARKER, serde_json::to_string(&header).unwrap()));
+            let disabled = !self.enabled || !layer.enabled;
+            for line in layer.eq.filter_lines() {
+                if disabled {
+                    lines.push(format!("#{}", line));
+                } else {
+                    lines.push(line);
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn from_lines(lines: &[&str]) -> Result<FilterBank, AppError> {
+        let inherit_all = !lines.contains(&NO_INHERIT_MARKER);
+        let capture = lines.iter().any(|l| l.trim_start_matches('#').trim() == CAPTURE_STAGE);
+        let mut bank = FilterBank::layers_from_lines(lines)?;
+        bank.inherit_all = inherit_all;
+        bank.direction = if capture { Direction::Capture } else { Direction::Render };
+        Ok(bank)
+    }
+
+    fn layers_from_lines(lines: &[&str]) -> Result<FilterBank, AppError> {
+        let headers: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.starts_with(LAYER_MARKER))
+            .map(|(i, _)| i)
+            .collect();
+        if headers.is_empty() {
+            // written before layers existed: the whole section is one layer
+            return Ok(FilterBank::new(EqState::from_lines(&lines.to_vec())?));
+        }
+
+        let mut layers: Vec<Layer> = vec![];
+        let mut selected_layer = 0;
+        for (n, start) in headers.iter().enumerate() {
+            let raw_header = lines[*start][LAYER_MARKER.len()..].trim();
+            let header: LayerHeader = serde_json::from_str(raw_header)
+                .map_err(|_| err(format!("Malformed layer line: {}", lines[*start])))?;
+            let end = headers.get(n + 1).cloned().unwrap_or(lines.len());
+            let mut eq = EqState::from_lines(&lines[start + 1..end].to_vec())?;
+            eq.preamp = header.preamp;
+            if header.selected {
+                selected_layer = layers.len();
+            }
+            layers.push(Layer { name: header.name, enabled: header.enabled, gain: header.gain, eq });
+        }
+        Ok(FilterBank { enabled: true, layers, selected_layer, inherit_all: true, direction: Direction::Render })
+    }
+
     pub fn from_apo_raw(raw: &str) -> Result<DeviceFilterMapping, AppError> {
         let mut mappings: DeviceFilterMapping = DeviceFilterMapping::new();
         let lines = raw.split("\n");
         let device_grouping: Vec<Box<Vec<&str>>> = lines
             .fold(vec![], |mut acc, line| {
-                if line.contains("Device:") {
+                if line.trim().trim_start_matches('#').trim_start().starts_with("Device:") {
                     acc.push(Box::from(vec![]));
                 }
                 match acc.last_mut() {
@@ -276,31 +534,81 @@ impl FilterBank {
             match d.first() {
                 Some(device_line) => {
                     enabled = !device_line.starts_with("#");
-                    device_name = device_line
-                        .replace("Device: ", "")
-                        .replace("#", "")
-                        .trim()
-                        .to_string();
+                    device_name = DeviceSelector::parse(device_line).to_string();
                 },
                 None => {}
             }
-            let eq = EqState::from_lines(&d)?;
-            mappings.insert(device_name, FilterBank { enabled, eq });
+            let mut bank = FilterBank::from_lines(&d[1..])?;
+            bank.enabled = enabled;
+            mappings.insert(device_name, bank);
         }
 
         Ok(mappings)
     }
 }
 
+/// Sections in the order they are written: "all" first, then the rest sorted by name, so the
+/// result of APO's top-to-bottom evaluation does not depend on hash map ordering.
+pub fn ordered_sections(mapping: &DeviceFilterMapping) -> Vec<(&String, &FilterBank)> {
+    let mut sections: Vec<(&String, &FilterBank)> = mapping.iter().collect();
+    sections.sort_by_key(|(name, _)| (name.as_str() != ALL_DEVICES, name.as_str()));
+    sections
+}
+
+/// Whether any section is meant for a microphone. Until one is, no `Stage:` lines are written and
+/// APO runs every section in its default stages, post-mix and capture. Output sections are then
+/// pinned to post-mix, so they run the same either way.
+pub fn has_capture_sections(mapping: &DeviceFilterMapping) -> bool {
+    mapping.values().any(|bank| bank.direction == Direction::Capture)
+}
+
 pub fn mapping_to_apo(mapping: &DeviceFilterMapping) -> String {
-    let mut result = "# GENERATED FILE, DO NOT MODIFY\n# generated by eq+\n# schema v1\n".to_string();
-    for (device, m) in mapping {
+    // APO cannot exclude a device from "Device: all", so devices opting out of it are skipped
+    // with a condition on the endpoint GUID around the "all" bank instead. A disabled bank is
+    // commented out, so its device keeps getting "all".
+    let opted_out: Vec<String> = mapping
+        .iter()
+        .filter(|(name, bank)| name.as_str() != ALL_DEVICES && bank.enabled && !bank.inherit_all)
+        .flat_map(|(name, _)| DeviceSelector::parse(name).guids())
+        .collect();
+    let staged = has_capture_sections(mapping);
+
+    let mut result = "# GENERATED FILE, DO NOT MODIFY\n# generated by eq+\n# schema v2\n".to_string();
+    for (device, m) in ordered_sections(mapping) {
         let d = if m.enabled {
             format!("Device: {}", device)
         } else {
             format!("#Device: {}", device)
         };
-        result += format!("{}\n{}\n", d, m.eq.to_apo(!m.enabled)).as_str();
+        let wrap = device == ALL_DEVICES && m.enabled && !opted_out.is_empty();
+        result += format!("{}\n", d).as_str();
+        if !m.inherit_all {
+            result += format!("{}\n", NO_INHERIT_MARKER).as_str();
+        }
+        if staged {
+            let stage = match m.direction {
+                Direction::Render => RENDER_STAGE,
+                Direction::Capture => CAPTURE_STAGE,
+            };
+            let comment = if m.enabled { "" } else { "#" };
+            result += format!("{}{}\n", comment, stage).as_str();
+        }
+        if wrap {
+            let conditions: Vec<String> = opted_out
+                .iter()
+                .flat_map(|g| {
+                    let mut variants = vec![g.to_lowercase(), g.to_uppercase()];
+                    variants.dedup();
+                    variants
+                })
+                .map(|g| format!("deviceGuid != \"{}\"", g))
+                .collect();
+            result += format!("If: {}\n", conditions.join(" and ")).as_str();
+        }
+        result += format!("{}\n", m.to_apo()).as_str();
+        if wrap {
+            result += "EndIf:\n";
+        }
     }
     result
 }
@@ -309,6 +617,11 @@ fn err(msg: String) -> AppError {
     AppError { err_type: ErrorType::InvalidConfig, message: msg }
 }
 
+/// Prefixes a parse error with the (1-based) line of the imported file it came from.
+pub fn at_line(number: usize, e: AppError) -> AppError {
+    AppError { err_type: e.err_type, message: format!("Line {}: {}", number, e.message) }
+}
+
 #[test]
 fn test_filter_mapping() {
     let input = "
@@ -329,19 +642,139 @@ Preamp: -5.0 dB
     println!("{:?}", result);
     let first = result.get(&"test-device {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}".to_string()).unwrap();
     assert_eq!(first.enabled, true);
-    assert_eq!(first.eq.preamp, -1.0f64);
-    assert_eq!(first.eq.filters.first().unwrap().frequency, 55f64);
+    assert_eq!(first.eq().preamp, -1.0f64);
+    assert_eq!(first.eq().filters.first().unwrap().frequency, 55f64);
 
     let second = result.get(&"other device {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}".to_string()).unwrap();
     assert_eq!(second.enabled, true);
-    assert_eq!(second.eq.preamp, -5.0f64);
+    assert_eq!(second.eq().preamp, -5.0f64);
 
     let third = result.get(&"third device {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}".to_string()).unwrap();
     assert_eq!(third.enabled, false);
-    assert_eq!(third.eq.preamp, -3.0f64);
-    assert_eq!(third.eq.filters.first().unwrap().frequency, 41f64);
-    assert_eq!(third.eq.filters.first().unwrap().q, 0.1f64);
+    assert_eq!(third.eq().preamp, -3.0f64);
+    assert_eq!(third.eq().filters.first().unwrap().frequency, 41f64);
+    assert_eq!(third.eq().filters.first().unwrap().q, 0.1f64);
 
     let serialized = mapping_to_apo(&result);
     println!("{}", serialized);
 }
+
+#[test]
+fn test_layer_round_trip() {
+    let mut bank = FilterBank::new(EqState::default());
+    bank.eq_mut().preamp = -4.0;
+    let mut taste = Layer::new("Bass boost", EqState {
+        preamp: -2.0,
+        filters: vec![FilterParams { id: String::from("1"), frequency: 80.0, gain: 4.0, q: 0.7, filter_type: FilterType::LowShelf }],
+        graphic_eq: vec![GraphicEqPoint { frequency: 20.0, gain: 1.0 }, GraphicEqPoint { frequency: 20000.0, gain: -1.5 }],
+    });
+    taste.gain = -1.0;
+    bank.layers.push(taste);
+    bank.layers.push(Layer { enabled: false, ..Layer::new("Treble tilt", EqState::default()) });
+    bank.selected_layer = 1;
+
+    let flat = bank.flattened();
+    assert_eq!(flat.preamp, -7.0);
+    assert_eq!(flat.filters.len(), 5);
+    assert_eq!(flat.filters.last().unwrap().id, "5");
+
+    let mut mapping = DeviceFilterMapping::new();
+    mapping.insert("all".to_string(), bank);
+    let raw = mapping_to_apo(&mapping);
+    assert!(raw.contains("Preamp: -7.0 dB"));
+    assert!(raw.contains("#Filter 1: ON PK Fc 48.0 Hz"));
+    assert!(raw.contains("\nGraphicEQ: 20 1; 20000 -1.5\n"));
+
+    let parsed = FilterBank::from_apo_raw(&raw).unwrap();
+    let bank = parsed.get("all").unwrap();
+    assert_eq!(bank.layers.len(), 3);
+    assert_eq!(bank.selected_layer, 1);
+    assert_eq!(bank.eq().preamp, -2.0);
+    assert_eq!(bank.layers[1].gain, -1.0);
+    assert!(matches!(bank.layers[1].eq.filters[0].filter_type, FilterType::LowShelf));
+    assert_eq!(bank.layers[1].eq.graphic_eq.len(), 2);
+    assert!(!bank.layers[2].enabled);
+    assert_eq!(bank.layers[2].eq.filters.len(), 4);
+    assert_eq!(bank.flattened().preamp, -7.0);
+}
+
+#[test]
+fn test_process_filter_type() {
+    let parsed = |raw: &str| process_filter_type(raw).unwrap();
+    assert_eq!(parsed("LS"), FilterType::LowShelf);
+    assert_eq!(parsed("LSC"), FilterType::LowShelf);
+    assert_eq!(parsed("HSC"), FilterType::HighShelf);
+    assert_eq!(parsed("BP"), FilterType::BandPass);
+    assert_eq!(parsed("LPQ"), FilterType::LowPass);
+    assert!(process_filter_type("LSQ").is_err());
+
+    for filter_type in [FilterType::LowShelf, FilterType::HighShelf, FilterType::BandPass, FilterType::Notch, FilterType::AllPass] {
+        let line = FilterParams { id: "1".to_string(), frequency: 100.0, gain: 3.0, q: 0.7, filter_type }.to_apo_line();
+        assert_eq!(parsed(line.split(' ').nth(3).unwrap()), filter_type);
+    }
+}
+
+#[test]
+fn test_all_bank_inheritance() {
+    let mut mapping = FilterBank::default();
+    let mut headphones = FilterBank::new(EqState { preamp: -1.0, filters: vec![], graphic_eq: vec![] });
+    headphones.inherit_all = false;
+    mapping.insert("Headphones USB DAC {ABCD-1234}".to_string(), headphones);
+    mapping.insert("Speakers {EF01}".to_string(), FilterBank::new(EqState { preamp: -2.0, filters: vec![], graphic_eq: vec![] }));
+
+    let raw = mapping_to_apo(&mapping);
+    let lines: Vec<&str> = raw.lines().collect();
+    assert_eq!(lines[3], "Device: all");
+    assert_eq!(lines[4], "If: deviceGuid != \"{abcd-1234}\" and deviceGuid != \"{ABCD-1234}\"");
+    assert!(raw.contains("EndIf:\nDevice: Headphones USB DAC {ABCD-1234}\n# eq+ inherit: false\n"));
+
+    let parsed = FilterBank::from_apo_raw(&raw).unwrap();
+    assert!(!parsed.get("Headphones USB DAC {ABCD-1234}").unwrap().inherit_all);
+    assert!(parsed.get("Speakers {EF01}").unwrap().inherit_all);
+    assert_eq!(parsed.get("all").unwrap().eq().filters.len(), 4);
+
+    mapping.get_mut("Headphones USB DAC {ABCD-1234}").unwrap().enabled = false;
+    let raw = mapping_to_apo(&mapping);
+    assert!(!raw.contains("If: deviceGuid"));
+    assert!(!raw.contains("EndIf:"));
+}
+
+#[test]
+fn test_capture_stage() {
+    let mut mapping = FilterBank::default();
+    mapping.insert("Speakers {EF01}".to_string(), FilterBank::new(EqState::default()));
+    assert!(!mapping_to_apo(&mapping).contains("Stage:"));
+
+    let mut microphone = FilterBank::new(EqState { preamp: 3.0, filters: vec![], graphic_eq: vec![] });
+    microphone.direction = Direction::Capture;
+    microphone.enabled = false;
+    mapping.insert("Microphone {0101}".to_string(), microphone);
+    let raw = mapping_to_apo(&mapping);
+    assert!(raw.contains("Device: all\nStage: post-mix\n"));
+    assert!(raw.contains("#Device: Microphone {0101}\n#Stage: capture\n"));
+    assert!(raw.contains("Device: Speakers {EF01}\nStage: post-mix\n"));
+
+    let parsed = FilterBank::from_apo_raw(&raw).unwrap();
+    let microphone = parsed.get("Microphone {0101}").unwrap();
+    assert_eq!(microphone.direction, Direction::Capture);
+    assert_eq!(microphone.eq().preamp, 3.0);
+    assert_eq!(parsed.get("all").unwrap().direction, Direction::Render);
+    assert_eq!(mapping_to_apo(&parsed), raw);
+}
+
+#[test]
+fn test_graphic_eq() {
+    let points = process_graphic_eq_line("GraphicEQ: 20 -6.3; 100 0;1000 3 ; ").unwrap();
+    assert_eq!(points.len(), 3);
+    assert_eq!(graphic_eq_gain(&points, 10.0), -6.3);
+    assert_eq!(graphic_eq_gain(&points, 20000.0), 3.0);
+    assert!((graphic_eq_gain(&points, 316.227766) - 1.5).abs() < 1e-6);
+    assert_eq!(graphic_eq_line(&points), "GraphicEQ: 20 -6.3; 100 0; 1000 3");
+
+    let combined = combine_graphic_eq(&[(&points, 1.0), (&[GraphicEqPoint { frequency: 50.0, gain: 1.0 }], 2.0)]);
+    assert_eq!(combined.len(), 4);
+    assert_eq!(combined[0], GraphicEqPoint { frequency: 20.0, gain: -4.3 });
+
+    assert!(process_graphic_eq_line("GraphicEQ: 20").is_err());
+    assert!(process_graphic_eq_line("GraphicEQ: 20 abc; 30 1").is_err());
+}
@@ -0,0 +1,120 @@
+//! Bounded undo/redo history of `DeviceFilterMapping` snapshots.
+//!
+//! Dragging a filter handle produces a stream of tiny edits; edits that share a group key and
+//! arrive within `GROUP_WINDOW` of each other collapse into a single undo step.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::changes::Change;
+use crate::filters::DeviceFilterMapping;
+
+const DEFAULT_LIMIT: usize = 100;
+const GROUP_WINDOW: Duration = Duration::from_millis(1000);
+
+struct Entry {
+    mapping: DeviceFilterMapping,
+    group: Option<String>,
+    last_edit: Instant,
+}
+
+pub struct History {
+    undo: VecDeque<Entry>,
+    redo: Vec<DeviceFilterMapping>,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_LIMIT)
+    }
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History { undo: VecDeque::new(), redo: vec![], limit }
+    }
+
+    /// Records the mapping as it was before an edit.
+    pub fn record(&mut self, previous: DeviceFilterMapping, group: Option<String>, now: Instant) {
+        self.redo.clear();
+        if let (Some(group), Some(last)) = (&group, self.undo.back_mut()) {
+            if last.group.as_ref() == Some(group) && now.duration_since(last.last_edit) < GROUP_WINDOW {
+                last.last_edit = now;
+                return;
+            }
+        }
+        self.undo.push_back(Entry { mapping: previous, group, last_edit: now });
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Stops the current group so the next edit starts a new undo step, e.g. when a drag ends.
+    pub fn end_group(&mut self) {
+        if let Some(last) = self.undo.back_mut() {
+            last.group = None;
+        }
+    }
+
+    pub fn undo(&mut self, current: DeviceFilterMapping) -> Option<DeviceFilterMapping> {
+        let entry = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(entry.mapping)
+    }
+
+    pub fn redo(&mut self, current: DeviceFilterMapping, now: Instant) -> Option<DeviceFilterMapping> {
+        let next = self.redo.pop()?;
+        self.undo.push_back(Entry { mapping: current, group: None, last_edit: now });
+        Some(next)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// The history group for a batch of changes; only single continuous edits are grouped.
+pub fn group_for(changes: &[Change]) -> Option<String> {
+    match changes {
+        [Change::ModifyFilter { device, filter }] => Some(format!("modify_filter:{}:{}", device, filter.id)),
+        [Change::ModifyPreamp { device, .. }] => Some(format!("modify_preamp:{}", device)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_history_grouping() {
+    let base = crate::filters::FilterBank::default();
+    let with_preamp = |preamp: f64| {
+        let mut m = base.clone();
+        m.get_mut("all").unwrap().eq_mut().preamp = preamp;
+        m
+    };
+    let start = Instant::now();
+    let mut history = History::new(2);
+
+    history.record(with_preamp(0.0), Some("drag".to_string()), start);
+    history.record(with_preamp(-1.0), Some("drag".to_string()), start + Duration::from_millis(100));
+    history.record(with_preamp(-2.0), Some("drag".to_string()), start + Duration::from_millis(200));
+
+    let undone = history.undo(with_preamp(-3.0)).unwrap();
+    assert_eq!(undone.get("all").unwrap().eq().preamp, 0.0);
+    assert!(!history.can_undo());
+
+    let redone = history.redo(undone, start).unwrap();
+    assert_eq!(redone.get("all").unwrap().eq().preamp, -3.0);
+    assert!(history.can_undo());
+    assert!(!history.can_redo());
+
+    history.record(with_preamp(1.0), None, start);
+    history.record(with_preamp(2.0), None, start);
+    history.record(with_preamp(3.0), None, start);
+    assert_eq!(history.undo(with_preamp(4.0)).unwrap().get("all").unwrap().eq().preamp, 3.0);
+    assert_eq!(history.undo(with_preamp(3.0)).unwrap().get("all").unwrap().eq().preamp, 2.0);
+    assert!(history.undo(with_preamp(2.0)).is_none());
+}
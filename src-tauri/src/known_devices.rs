@@ -0,0 +1,148 @@
+//! Registry of every audio endpoint eq+ has seen, keyed by endpoint GUID.
+//!
+//! Windows renames endpoints freely (e.g. `Speakers (2- Realtek Audio)` after a driver update) while
+//! the GUID stays the same. Remembering the last name seen for each GUID lets us spot renames and
+//! re-link mapping sections that were written against the old name.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::DeviceInfo;
+use crate::changes::Change;
+use crate::filters::DeviceFilterMapping;
+use crate::selector::{device_string, DeviceSelector};
+
+pub const KNOWN_DEVICES_FILE: &str = "known_devices.json";
+const KNOWN_DEVICES_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnownDevice {
+    pub guid: String,
+    pub name: String,
+    #[serde(default)]
+    pub previous_names: Vec<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnownDevices {
+    pub version: u32,
+    pub devices: BTreeMap<String, KnownDevice>,
+}
+
+impl Default for KnownDevices {
+    fn default() -> Self {
+        KnownDevices { version: KNOWN_DEVICES_VERSION, devices: BTreeMap::new() }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DeviceRename {
+    pub guid: String,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// A section that could not follow its device's rename because a section already goes by the new
+/// name.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SkippedRelink {
+    pub from: String,
+    pub to: String,
+}
+
+impl KnownDevices {
+
+    /// Records the currently present devices and returns the ones whose name changed since last seen.
+    pub fn observe(&mut self, devices: &[DeviceInfo], now: u64) -> Vec<DeviceRename> {
+        let mut renames = vec![];
+        for device in devices {
+            let key = device.guid.to_lowercase();
+            match self.devices.get_mut(&key) {
+                Some(known) => {
+                    if known.name != device.name {
+                        renames.push(DeviceRename { guid: device.guid.clone(), old_name: known.name.clone(), new_name: device.name.clone() });
+                        let old = std::mem::replace(&mut known.name, device.name.clone());
+                        if !known.previous_names.contains(&old) {
+                            known.previous_names.push(old);
+                        }
+                    }
+                    known.last_seen = now;
+                },
+                None => {
+                    self.devices.insert(key, KnownDevice {
+                        guid: device.guid.clone(),
+                        name: device.name.clone(),
+                        previous_names: vec![],
+                        first_seen: now,
+                        last_seen: now,
+                    });
+                }
+            }
+        }
+        renames
+    }
+}
+
+/// Builds the changes that move sections written against a device's old name over to its new one,
+/// along with the renames skipped because the new name is already taken.
+///
+/// Only patterns that are exactly the old device string are rewritten; hand-written patterns are
+/// left alone since there is no telling what they were meant to match.
+pub fn relink_changes(mapping: &DeviceFilterMapping, renames: &[DeviceRename]) -> (Vec<Change>, Vec<SkippedRelink>) {
+    let mut changes = vec![];
+    let mut skipped = vec![];
+    for section in mapping.keys() {
+        let mut selector = DeviceSelector::parse(section);
+        let mut relinked = false;
+        for pattern in selector.patterns.iter_mut() {
+            for rename in renames {
+                if pattern.eq_ignore_ascii_case(&device_string(&rename.old_name, &rename.guid)) {
+                    *pattern = device_string(&rename.new_name, &rename.guid);
+                    relinked = true;
+                }
+            }
+        }
+        if !relinked {
+            continue;
+        }
+        let to = selector.to_string();
+        if mapping.contains_key(&to) {
+            skipped.push(SkippedRelink { from: section.clone(), to });
+        } else {
+            changes.push(Change::RenameDevice { from: section.clone(), to });
+        }
+    }
+    (changes, skipped)
+}
+
+#[test]
+fn test_relink_renamed_device() {
+    let guid = "{0.0.0.00000000}.{1234}".to_string();
+    let device = |name: &str| DeviceInfo { guid: guid.clone(), name: name.to_string(), apo_installed: true, is_default: false, direction: crate::backend::Direction::Render, format: None };
+
+    let mut known = KnownDevices::default();
+    assert!(known.observe(&[device("Speakers (Realtek Audio)")], 1).is_empty());
+    let renames = known.observe(&[device("Speakers (2- Realtek Audio)")], 2);
+    assert_eq!(renames.len(), 1);
+    assert_eq!(known.devices.get(&guid).unwrap().previous_names, vec!["Speakers (Realtek Audio)".to_string()]);
+
+    let mut mapping = crate::filters::FilterBank::default();
+    let old_section = device_string("Speakers (Realtek Audio)", &guid);
+    mapping.insert(old_section.clone(), mapping.get("all").unwrap().clone());
+
+    let (changes, skipped) = relink_changes(&mapping, &renames);
+    assert!(skipped.is_empty());
+    let updated = crate::changes::apply_changes(&mapping, &changes).unwrap();
+    assert!(!updated.contains_key(&old_section));
+    assert!(updated.contains_key(&device_string("Speakers (2- Realtek Audio)", &guid)));
+    assert!(updated.contains_key("all"));
+
+    let new_section = device_string("Speakers (2- Realtek Audio)", &guid);
+    mapping.insert(new_section.clone(), mapping.get("all").unwrap().clone());
+    let (changes, skipped) = relink_changes(&mapping, &renames);
+    assert!(changes.is_empty());
+    assert_eq!(skipped, vec![SkippedRelink { from: old_section, to: new_section }]);
+}
@@ -1,38 +1,79 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod ab;
+mod abx;
+mod auto_switch;
+mod autoeq;
+mod backend;
+mod blend;
+mod changes;
+mod dev;
+mod diagnostics;
 mod errors;
+mod export;
 mod filters;
+mod history;
+mod known_devices;
+mod peace;
+mod pipewire;
+mod presets;
+mod registry;
+mod response;
+mod rew;
+mod selector;
+mod storage;
 #[cfg(windows)]
 mod win32;
-#[cfg(not(windows))]
-mod dev;
+mod wine;
 
-use errors::{AppError, ErrorType};
+use ab::{AbSlots, DeviceAbSlots, Slot, AB_SLOTS_FILE};
+use abx::{AbxSession, AbxSource, AbxStatus, ABX_DIR};
+use auto_switch::{SwitchLog, SwitchLogEntry, SwitchRules, SWITCH_LOG_FILE, SWITCH_RULES_FILE};
+use autoeq::AutoEqFormat;
+use backend::{ApoStatus, AudioBackend, DeviceEvent, DeviceInfo, Direction};
+use changes::{Change, PatchOperation};
+use diagnostics::DeviceDiagnosis;
+use errors::{no_such_section, AppError, ErrorType};
+use export::ExportTarget;
 use filters::{FilterBank, DeviceFilterMapping};
-use std::{path::Path, fs::{self}, sync::Mutex};
-use tauri::generate_handler;
+use selector::{DeviceSelector, EffectiveChain, SectionMatch};
+use history::History;
+use known_devices::{KnownDevices, KNOWN_DEVICES_FILE};
+use peace::{PeaceStatus, PEACE_CONFIG};
+use presets::{Preset, PresetLibrary, PresetMetadata, PRESETS_DIR};
+use rew::RewEquipment;
+use std::{path::{Path, PathBuf}, fs::{self}, sync::{Arc, Mutex, MutexGuard}, time::Instant};
+use tauri::{generate_handler, Manager};
 use log::{info, warn, debug};
-#[cfg(windows)]
-use win32::device::DeviceInfo;
-#[cfg(windows)]
-use win32::config::get_equalizer_apo_config_dir;
-
-#[cfg(not(windows))]
-use dev::device::DeviceInfo;
-#[cfg(not(windows))]
-use dev::config::get_equalizer_apo_config_dir;
-
 use crate::filters::mapping_to_apo;
 
 const E_APO_CONFIG: &str = "config.txt";
 const EQPLUS_CONFIG: &str = "eqplus.txt";
 const INCLUDE_LINE: &str = "Include: eqplus.txt";
+const DEVICE_EVENT: &str = "device-event";
+const DEVICES_CHANGED_EVENT: &str = "devices-changed";
+const PROFILE_SWITCHED_EVENT: &str = "profile-switched";
+const RELINK_SKIPPED_EVENT: &str = "relink-skipped";
 
-#[derive(Default)]
 struct AppState {
+    backend: Arc<dyn AudioBackend>,
     config_dir: Mutex<String>,
     mapping: Mutex<DeviceFilterMapping>,
+    history: Mutex<History>,
+    abx: Mutex<Option<AbxSession>>,
+}
+
+impl AppState {
+    fn new(backend: Arc<dyn AudioBackend>) -> AppState {
+        AppState {
+            backend,
+            config_dir: Mutex::default(),
+            mapping: Mutex::default(),
+            history: Mutex::default(),
+            abx: Mutex::default(),
+        }
+    }
 }
 
 struct ErrorState {
@@ -41,7 +82,7 @@ struct ErrorState {
 
 fn check_config_dir(state: &AppState) -> Result<(), AppError> {
     info!("checking config dir...");
-    let dir_from_registry = get_equalizer_apo_config_dir().map_err(|e| {
+    let dir_from_registry = state.backend.config_dir().map_err(|e| {
         warn!("Invalid config directory: {}", e);
         match e.err_type {
             ErrorType::RegistryError => AppError { err_type: e.err_type, message: "Could not read config directory from registry. Is EqualizerAPO installed?".to_string() },
@@ -49,7 +90,8 @@ fn check_config_dir(state: &AppState) -> Result<(), AppError> {
         }
     })?;
     debug!("config dir: {}", dir_from_registry);
-    let path_to_config = Path::new(dir_from_registry.as_str()).join(EQPLUS_CONFIG);
+    // APO's own config.txt marks a valid directory; eqplus.txt does not exist before the first run
+    let path_to_config = Path::new(dir_from_registry.as_str()).join(E_APO_CONFIG);
     if !path_to_config.exists() {
         return Err(AppError { err_type: ErrorType::InvalidConfigDirectory, message: "Config directory was read from registry, but no config.txt file was found in it!".to_string() });
     }
@@ -84,6 +126,9 @@ fn check_config_file(state: &AppState) -> Result<(), AppError> {
         let augmented = format!("{}\n{}", apo_config, INCLUDE_LINE);
         fs::write(path, augmented)?;
     }
+    if peace::is_included(&apo_config) {
+        warn!("{} also includes Peace's configuration, both EQs will be applied", E_APO_CONFIG);
+    }
     info!("...config file is ok");
     Ok(())
 }
@@ -117,64 +162,476 @@ async fn get_state(state: tauri::State<'_, AppState>) -> Result<DeviceFilterMapp
 #[tauri::command]
 async fn modify_filter(device: String, filter: filters::FilterParams, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
     debug!("modifying filter {} for device {} -> freq: {:.3} | gain: {:.3} | q: {:.3}", filter.id, device, filter.frequency, filter.gain, filter.q);
-    let mappings = &mut state.mapping.lock().unwrap();
-    let device_mapping = mappings.get_mut(&device).ok_or(AppError{ err_type: ErrorType::BadArguments, message: format!("Could not find device with name {}", device) })?;
-    let new_filters: Vec<filters::FilterParams> = device_mapping.eq.filters
-        .iter()
-        .map(|f| {
-            if f.id == filter.id {
-                filter.clone()
-            } else {
-                f.clone()
-            }
-        })
-        .collect();
-
-    device_mapping.eq.filters = new_filters;
-
-    let path = state.config_dir.lock().unwrap();
-    update_config_file(&path, mappings)?;
-    Ok(())
+    commit_changes(&state, &[Change::ModifyFilter { device, filter }])
 }
 
 #[tauri::command]
 async fn add_filter(device: String, filter: filters::FilterParams, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
-    let mappings = &mut state.mapping.lock().unwrap();
-    let device_mapping = mappings.get_mut(&device).ok_or(AppError{ err_type: ErrorType::BadArguments, message: format!("Could not find device with name {}", device)})?;
-    device_mapping.eq.filters.push(filter);
-    let path = state.config_dir.lock().unwrap();
-    update_config_file(&path, mappings)?;
-    Ok(())
+    commit_changes(&state, &[Change::AddFilter { device, filter }])
 }
 
 #[tauri::command]
 async fn remove_filter(device: String, id: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
-    let mappings = &mut state.mapping.lock().unwrap();
-    let device_mapping = mappings.get_mut(&device).ok_or(AppError{ err_type: ErrorType::BadArguments, message: format!("Could not find device with name {}", device)})?;
-    let new_filters = device_mapping.eq.filters
-        .clone()
-        .into_iter()
-        .filter(|x| x.id == id)
-        .collect();
-    device_mapping.eq.filters = new_filters;
-    let path = state.config_dir.lock().unwrap();
-    update_config_file(&path, mappings)?;
-    Ok(())
+    commit_changes(&state, &[Change::RemoveFilter { device, id }])
 }
 
 #[tauri::command]
 async fn modify_preamp(device: String, preamp: f64, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
-    let mappings = &mut state.mapping.lock().unwrap();
-    let device_mapping = mappings.get_mut(&device).ok_or(AppError{ err_type: ErrorType::BadArguments, message: format!("Could not find device with name {}", device)})?;
-    device_mapping.eq.preamp = preamp;
-    let path = state.config_dir.lock().unwrap();
-    update_config_file(&path, mappings)?;
-    Ok(())
+    commit_changes(&state, &[Change::ModifyPreamp { device, preamp }])
+}
+
+#[tauri::command]
+async fn apply_changes(changes: Vec<Change>, state: tauri::State<'_, AppState>) -> Result<DeviceFilterMapping, AppError> {
+    debug!("applying {} changes", changes.len());
+    commit_changes(&state, &changes)?;
+    Ok(state.mapping.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn apply_patch(patch: Vec<PatchOperation>, state: tauri::State<'_, AppState>) -> Result<DeviceFilterMapping, AppError> {
+    debug!("applying patch with {} operations", patch.len());
+    let _abx = ensure_no_abx(&state)?;
+    let mut mappings = state.mapping.lock().unwrap();
+    let updated = changes::apply_patch(&mappings, &patch)?;
+    commit_mapping(&state, &mut mappings, updated, None)?;
+    Ok(mappings.clone())
+}
+
+#[tauri::command]
+async fn add_layer(device: String, name: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::AddLayer { device, name }])
+}
+
+#[tauri::command]
+async fn remove_layer(device: String, name: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::RemoveLayer { device, name }])
+}
+
+#[tauri::command]
+async fn rename_layer(device: String, from: String, to: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::RenameLayer { device, from, to }])
+}
+
+#[tauri::command]
+async fn set_layer_enabled(device: String, name: String, enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::SetLayerEnabled { device, name, enabled }])
+}
+
+#[tauri::command]
+async fn set_layer_gain(device: String, name: String, gain: f64, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    let group = Some(format!("set_layer_gain:{}:{}", device, name));
+    commit_changes_in_group(&state, &[Change::SetLayerGain { device, name, gain }], group)
+}
+
+#[tauri::command]
+async fn move_layer(device: String, name: String, index: usize, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::MoveLayer { device, name, index }])
+}
+
+#[tauri::command]
+async fn select_layer(device: String, name: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::SelectLayer { device, name }])
+}
+
+#[tauri::command]
+async fn undo(state: tauri::State<'_, AppState>) -> Result<DeviceFilterMapping, AppError> {
+    let _abx = ensure_no_abx(&state)?;
+    let mut mappings = state.mapping.lock().unwrap();
+    let mut history = state.history.lock().unwrap();
+    let previous = history.undo(mappings.clone()).ok_or(AppError { err_type: ErrorType::BadArguments, message: "Nothing to undo".to_string() })?;
+    if let Err(e) = update_config_file(&state, &previous) {
+        history.redo(previous, Instant::now());
+        return Err(e);
+    }
+    *mappings = previous;
+    Ok(mappings.clone())
+}
+
+#[tauri::command]
+async fn redo(state: tauri::State<'_, AppState>) -> Result<DeviceFilterMapping, AppError> {
+    let _abx = ensure_no_abx(&state)?;
+    let mut mappings = state.mapping.lock().unwrap();
+    let mut history = state.history.lock().unwrap();
+    let next = history.redo(mappings.clone(), Instant::now()).ok_or(AppError { err_type: ErrorType::BadArguments, message: "Nothing to redo".to_string() })?;
+    if let Err(e) = update_config_file(&state, &next) {
+        history.undo(next);
+        return Err(e);
+    }
+    *mappings = next;
+    Ok(mappings.clone())
+}
+
+#[tauri::command]
+fn end_history_group(state: tauri::State<'_, AppState>) {
+    state.history.lock().unwrap().end_group();
+}
+
+#[tauri::command]
+async fn add_device_mapping(guid: String, state: tauri::State<'_, AppState>) -> Result<String, AppError> {
+    let device = find_device(&state, &guid)?;
+    let name = DeviceSelector::for_device(&device).to_string();
+    info!("adding mapping {} for device {}", name, guid);
+    commit_changes(&state, &[Change::AddDevice { device: name.clone(), direction: device.direction }])?;
+    Ok(name)
+}
+
+#[tauri::command]
+async fn remove_device_mapping(device: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    info!("removing mapping {}", device);
+    commit_changes(&state, &[Change::RemoveDevice { device }])
+}
+
+#[tauri::command]
+async fn set_device_enabled(device: String, enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::SetDeviceEnabled { device, enabled }])
+}
+
+#[tauri::command]
+async fn rename_device_mapping(from: String, to: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    info!("renaming mapping {} -> {}", from, to);
+    commit_changes(&state, &[Change::RenameDevice { from: from.clone(), to: to.clone() }])?;
+    rename_ab_slots(&app, &from, &to)
+}
+
+#[tauri::command]
+async fn copy_device_eq(from: String, to: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::CopyDeviceEq { from, to }])
+}
+
+#[tauri::command]
+async fn query_devices(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Vec<DeviceInfo>, AppError> {
+    let devices = state.backend.enumerate()?;
+    sync_known_devices(&app, &state, &devices)?;
+    Ok(devices)
+}
+
+#[tauri::command]
+async fn get_apo_status(state: tauri::State<'_, AppState>) -> Result<ApoStatus, AppError> {
+    Ok(state.backend.apo_status())
+}
+
+/// Checks the device's effect slots and the config directory, with a hint for every problem found.
+#[tauri::command]
+async fn diagnose_device(guid: String, state: tauri::State<'_, AppState>) -> Result<DeviceDiagnosis, AppError> {
+    let device = find_device(&state, &guid)?;
+    let fx_slots = state.backend.apo_slots(&device)?;
+    let config_dir = state.config_dir.lock().unwrap().clone();
+    let diagnosis = diagnostics::diagnose(device, fx_slots, &config_dir, state.backend.config_dir());
+    info!("diagnosed {}: {} issues", guid, diagnosis.issues.len());
+    Ok(diagnosis)
+}
+
+#[tauri::command]
+async fn get_known_devices(app: tauri::AppHandle) -> Result<KnownDevices, AppError> {
+    storage::read_json(&app_data_dir(&app)?.join(KNOWN_DEVICES_FILE))
+}
+
+#[tauri::command]
+async fn get_switch_rules(app: tauri::AppHandle) -> Result<SwitchRules, AppError> {
+    storage::read_json(&app_data_dir(&app)?.join(SWITCH_RULES_FILE))
+}
+
+#[tauri::command]
+async fn set_switch_rules(rules: SwitchRules, app: tauri::AppHandle) -> Result<(), AppError> {
+    info!("saving {} profile switch rules (enabled: {})", rules.rules.len(), rules.enabled);
+    rules.validate()?;
+    storage::write_json(&app_data_dir(&app)?.join(SWITCH_RULES_FILE), &rules)
+}
+
+#[tauri::command]
+async fn get_switch_log(app: tauri::AppHandle) -> Result<SwitchLog, AppError> {
+    storage::read_json(&app_data_dir(&app)?.join(SWITCH_LOG_FILE))
+}
+
+#[tauri::command]
+async fn get_section_matches(state: tauri::State<'_, AppState>) -> Result<Vec<SectionMatch>, AppError> {
+    let devices = state.backend.enumerate()?;
+    let mappings = state.mapping.lock().unwrap();
+    Ok(selector::match_sections(&mappings, &devices))
+}
+
+#[tauri::command]
+async fn save_preset(name: String, eq: filters::EqState, metadata: Option<PresetMetadata>, app: tauri::AppHandle) -> Result<Preset, AppError> {
+    info!("saving preset {}", name);
+    preset_library(&app)?.save(&name, eq, metadata, storage::now())
+}
+
+#[tauri::command]
+async fn get_preset(name: String, app: tauri::AppHandle) -> Result<Preset, AppError> {
+    preset_library(&app)?.load(&name)
+}
+
+#[tauri::command]
+async fn list_presets(query: Option<String>, app: tauri::AppHandle) -> Result<Vec<Preset>, AppError> {
+    preset_library(&app)?.list(query.as_deref())
+}
+
+#[tauri::command]
+async fn rename_preset(from: String, to: String, app: tauri::AppHandle) -> Result<Preset, AppError> {
+    info!("renaming preset {} -> {}", from, to);
+    preset_library(&app)?.rename(&from, &to, storage::now())
+}
+
+#[tauri::command]
+async fn delete_preset(name: String, app: tauri::AppHandle) -> Result<(), AppError> {
+    info!("deleting preset {}", name);
+    preset_library(&app)?.delete(&name)
+}
+
+#[tauri::command]
+async fn apply_preset(name: String, device: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    info!("applying preset {} to device {}", name, device);
+    let preset = preset_library(&app)?.load(&name)?;
+    commit_changes(&state, &[Change::SetEq { device, eq: preset.eq }])
+}
+
+/// Replaces the device's selected layer with an AutoEQ ParametricEQ.txt or GraphicEQ.txt.
+#[tauri::command]
+async fn import_autoeq(device: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    info!("importing AutoEQ file {} into device {}", path, device);
+    let eq = autoeq::import(&fs::read_to_string(&path)?)?;
+    commit_changes(&state, &[Change::SetEq { device, eq }])
+}
+
+/// The device's combined layers in AutoEQ's file format.
+#[tauri::command]
+async fn export_autoeq(device: String, format: AutoEqFormat, state: tauri::State<'_, AppState>) -> Result<String, AppError> {
+    let mappings = state.mapping.lock().unwrap();
+    let bank = mappings
+        .get(&device)
+        .ok_or_else(|| no_such_section(&device))?;
+    autoeq::export(&bank.flattened(), format)
+}
+
+/// Replaces the device's selected layer with the filters of a REW filter settings export.
+#[tauri::command]
+async fn import_rew(device: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    info!("importing REW filters {} into device {}", path, device);
+    let eq = rew::import(&fs::read_to_string(&path)?)?;
+    commit_changes(&state, &[Change::SetEq { device, eq }])
+}
+
+#[tauri::command]
+async fn export_rew(device: String, equipment: RewEquipment, state: tauri::State<'_, AppState>) -> Result<String, AppError> {
+    let mappings = state.mapping.lock().unwrap();
+    let bank = mappings
+        .get(&device)
+        .ok_or_else(|| no_such_section(&device))?;
+    rew::export(&bank.flattened(), equipment)
+}
+
+/// The device's combined layers in another EQ application's format.
+#[tauri::command]
+async fn export_eq(device: String, target: ExportTarget, state: tauri::State<'_, AppState>) -> Result<String, AppError> {
+    let mappings = state.mapping.lock().unwrap();
+    let bank = mappings
+        .get(&device)
+        .ok_or_else(|| no_such_section(&device))?;
+    export::export(&bank.flattened(), target)
+}
+
+#[tauri::command]
+async fn get_peace_status(state: tauri::State<'_, AppState>) -> Result<PeaceStatus, AppError> {
+    let config_dir = state.config_dir.lock().unwrap().clone();
+    let config = fs::read_to_string(Path::new(&config_dir).join(E_APO_CONFIG))?;
+    Ok(peace::status(&config, Path::new(&config_dir).join(PEACE_CONFIG).exists()))
 }
 
+/// Imports a .peace preset as one undoable step, optionally switching Peace's own config off so
+/// the two EQs don't stack. Returns what could not be imported.
 #[tauri::command]
-async fn query_devices() -> Result<Vec<DeviceInfo>, AppError> {
-    DeviceInfo::enumerate()
+async fn import_peace(path: String, disable_peace: bool, state: tauri::State<'_, AppState>) -> Result<Vec<String>, AppError> {
+    info!("importing Peace preset {}", path);
+    let mut imported = peace::import(&fs::read_to_string(&path)?)?;
+    peace::resolve_devices(&mut imported, &state.backend.enumerate()?);
+    let mut changes = vec![];
+    {
+        let mappings = state.mapping.lock().unwrap();
+        for (device, eq) in imported.banks {
+            if !mappings.contains_key(&device) {
+                changes.push(Change::AddDevice { device: device.clone(), direction: Direction::Render });
+            }
+            changes.push(Change::SetEq { device, eq });
+        }
+    }
+    commit_changes(&state, &changes)?;
+    if disable_peace {
+        set_peace_include(&state, false)?;
+    }
+    Ok(imported.skipped)
+}
+
+#[tauri::command]
+async fn set_peace_include_enabled(enabled: bool, state: tauri::State<'_, AppState>) -> Result<PeaceStatus, AppError> {
+    set_peace_include(&state, enabled)
+}
+
+fn set_peace_include(state: &AppState, enabled: bool) -> Result<PeaceStatus, AppError> {
+    info!("{} Peace include in {}", if enabled { "restoring" } else { "disabling" }, E_APO_CONFIG);
+    let config_dir = state.config_dir.lock().unwrap().clone();
+    let path = Path::new(&config_dir).join(E_APO_CONFIG);
+    let config = fs::read_to_string(&path)?;
+    let updated = if enabled { peace::enable_include(&config) } else { peace::disable_include(&config) };
+    if updated != config {
+        fs::write(&path, &updated)?;
+    }
+    Ok(peace::status(&updated, Path::new(&config_dir).join(PEACE_CONFIG).exists()))
+}
+
+#[tauri::command]
+async fn get_ab_slots(device: String, app: tauri::AppHandle) -> Result<AbSlots, AppError> {
+    let all: DeviceAbSlots = storage::read_json(&app_data_dir(&app)?.join(AB_SLOTS_FILE))?;
+    Ok(all.get(&device).cloned().unwrap_or_default())
+}
+
+/// Stores an EQ in a slot; without one, the device's current EQ is captured.
+#[tauri::command]
+async fn set_ab_slot(device: String, slot: Slot, eq: Option<filters::EqState>, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<AbSlots, AppError> {
+    let current = state.mapping.lock().unwrap().get(&device).map(|bank| bank.eq().clone());
+    let sample_rate = section_sample_rate(&state, &device);
+    update_ab_slots(&app, &device, |slots| {
+        let eq = match (eq, current) {
+            (Some(eq), _) => eq,
+            (None, Some(current)) => slots.capture(&current, sample_rate),
+            (None, None) => return Err(no_such_section(&device)),
+        };
+        slots.slots.insert(slot, eq);
+        Ok(())
+    })
+}
+
+#[tauri::command]
+async fn clear_ab_slot(device: String, slot: Slot, app: tauri::AppHandle) -> Result<AbSlots, AppError> {
+    update_ab_slots(&app, &device, |slots| {
+        slots.slots.remove(&slot);
+        if slots.active == Some(slot) {
+            slots.active = None;
+        }
+        Ok(())
+    })
+}
+
+#[tauri::command]
+async fn get_ab_loudness_offsets(device: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<std::collections::BTreeMap<Slot, f64>, AppError> {
+    let sample_rate = section_sample_rate(&state, &device);
+    let slots = get_ab_slots(device, app).await?;
+    Ok(slots.loudness_offsets(sample_rate))
+}
+
+/// Writes the chosen slot to the device's bank, optionally loudness matched against the other slots.
+#[tauri::command]
+async fn switch_ab_slot(device: String, slot: Slot, loudness_match: bool, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<AbSlots, AppError> {
+    debug!("switching device {} to slot {:?} (loudness match: {})", device, slot, loudness_match);
+    let sample_rate = section_sample_rate(&state, &device);
+    update_ab_slots(&app, &device, |slots| {
+        let eq = slots.output_for(slot, loudness_match, sample_rate)?;
+        commit_changes(&state, &[Change::SetEq { device: device.clone(), eq }])?;
+        slots.active = Some(slot);
+        Ok(())
+    })
+}
+
+#[tauri::command]
+async fn start_abx(device: String, a: filters::EqState, b: filters::EqState, trials: usize, state: tauri::State<'_, AppState>) -> Result<AbxStatus, AppError> {
+    if !state.mapping.lock().unwrap().contains_key(&device) {
+        return Err(no_such_section(&device));
+    }
+    let session = AbxSession::new(device, a, b, trials, storage::now())?;
+    info!("starting ABX session {} with {} trials", session.id, trials);
+    // held from the first trial config on, so no edit slips in before the session is stored
+    let mut abx = state.abx.lock().unwrap();
+    play_abx(&state, &session, AbxSource::X)?;
+    let status = session.status();
+    *abx = Some(session);
+    Ok(status)
+}
+
+#[tauri::command]
+async fn get_abx_status(state: tauri::State<'_, AppState>) -> Result<Option<AbxStatus>, AppError> {
+    Ok(state.abx.lock().unwrap().as_ref().map(|s| s.status()))
+}
+
+#[tauri::command]
+async fn abx_play(source: AbxSource, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    let session = state.abx.lock().unwrap();
+    let session = session.as_ref().ok_or(no_abx_session())?;
+    play_abx(&state, session, source)
+}
+
+#[tauri::command]
+async fn abx_answer(answer: AbxSource, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<AbxStatus, AppError> {
+    let mut guard = state.abx.lock().unwrap();
+    let session = guard.as_mut().ok_or(no_abx_session())?;
+    session.answer(answer, storage::now())?;
+    if session.is_finished() {
+        finish_abx(&app, &state, session)?;
+        let results = session.results();
+        info!("ABX session {} finished: {}/{} correct, p = {:.4}", session.id, results.correct, results.trials, results.p_value);
+    } else {
+        play_abx(&state, session, AbxSource::X)?;
+    }
+    Ok(session.status())
+}
+
+#[tauri::command]
+async fn abort_abx(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<AbxStatus, AppError> {
+    let mut guard = state.abx.lock().unwrap();
+    let session = guard.as_mut().ok_or(no_abx_session())?;
+    info!("aborting ABX session {}", session.id);
+    session.abort(storage::now());
+    finish_abx(&app, &state, session)?;
+    Ok(session.status())
+}
+
+#[tauri::command]
+async fn list_abx_sessions(app: tauri::AppHandle) -> Result<Vec<AbxSession>, AppError> {
+    let dir = app_data_dir(&app)?.join(ABX_DIR);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut sessions: Vec<AbxSession> = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        match storage::load_json(&path) {
+            Ok(session) => sessions.push(session),
+            Err(e) => warn!("Skipping unreadable ABX session {}: {}", path.display(), e),
+        }
+    }
+    sessions.sort_by_key(|s| s.created);
+    Ok(sessions)
+}
+
+/// Writes a blend of two EQs to the device, `amount` going from 0 (all `a`) to 100 (all `b`).
+#[tauri::command]
+async fn morph_eq(device: String, a: filters::EqState, b: filters::EqState, amount: f64, state: tauri::State<'_, AppState>) -> Result<filters::EqState, AppError> {
+    let eq = blend::morph(&a, &b, amount / 100.0);
+    let group = Some(format!("morph_eq:{}", device));
+    commit_changes_in_group(&state, &[Change::SetEq { device, eq: eq.clone() }], group)?;
+    Ok(eq)
+}
+
+/// Writes `eq` to the device with every gain scaled to `depth` percent and the preamp recomputed.
+#[tauri::command]
+async fn set_eq_depth(device: String, eq: filters::EqState, depth: f64, state: tauri::State<'_, AppState>) -> Result<filters::EqState, AppError> {
+    if !(0.0..=200.0).contains(&depth) {
+        return Err(AppError { err_type: ErrorType::BadArguments, message: format!("EQ depth must be between 0 and 200%, got {}", depth) });
+    }
+    let scaled = blend::scale_depth(&eq, depth / 100.0, section_sample_rate(&state, &device));
+    let group = Some(format!("set_eq_depth:{}", device));
+    commit_changes_in_group(&state, &[Change::SetEq { device, eq: scaled.clone() }], group)?;
+    Ok(scaled)
+}
+
+#[tauri::command]
+async fn set_inherit_all(device: String, inherit: bool, state: tauri::State<'_, AppState>) -> Result<(), AppError> {
+    commit_changes(&state, &[Change::SetInheritAll { device, inherit }])
+}
+
+/// The chain a device actually ends up with once "all" and its own sections are combined.
+#[tauri::command]
+async fn get_effective_chain(guid: String, state: tauri::State<'_, AppState>) -> Result<EffectiveChain, AppError> {
+    let device = find_device(&state, &guid)?;
+    Ok(selector::resolve_chain(&state.mapping.lock().unwrap(), &device))
 }
 
 #[tauri::command]
@@ -195,8 +652,226 @@ fn quit(reason: String, app_handle: tauri::AppHandle) {
     app_handle.exit(0);
 }
 
-fn update_config_file(path: &str, mappings: &DeviceFilterMapping) -> Result<(), AppError> {
-    fs::write(Path::new(path).join(EQPLUS_CONFIG), mapping_to_apo(mappings))?;
+/// Writes eqplus.txt and hands the mapping to the backend. If the backend fails, the previous
+/// eqplus.txt is put back so the file keeps matching the mapping still in effect.
+fn update_config_file(state: &AppState, mappings: &DeviceFilterMapping) -> Result<(), AppError> {
+    let path = Path::new(state.config_dir.lock().unwrap().as_str()).join(EQPLUS_CONFIG);
+    let previous = fs::read_to_string(&path).ok();
+    fs::write(&path, mapping_to_apo(mappings))?;
+    if let Err(e) = state.backend.apply(mappings) {
+        match previous {
+            Some(contents) => fs::write(&path, contents)?,
+            None => fs::remove_file(&path)?,
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn app_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, AppError> {
+    let dir = app.path_resolver().app_data_dir().ok_or(AppError { err_type: ErrorType::GenericIoError, message: "Could not resolve the app data directory".to_string() })?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn update_ab_slots<F>(app: &tauri::AppHandle, device: &str, update: F) -> Result<AbSlots, AppError>
+    where F: FnOnce(&mut AbSlots) -> Result<(), AppError>
+{
+    let path = app_data_dir(app)?.join(AB_SLOTS_FILE);
+    let mut all: DeviceAbSlots = storage::read_json(&path)?;
+    let slots = all.entry(device.to_string()).or_default();
+    update(slots)?;
+    let updated = slots.clone();
+    storage::write_json(&path, &all)?;
+    Ok(updated)
+}
+
+/// Moves A/B slots along with a renamed section, since they are keyed by section name.
+fn rename_ab_slots(app: &tauri::AppHandle, from: &str, to: &str) -> Result<(), AppError> {
+    let path = app_data_dir(app)?.join(AB_SLOTS_FILE);
+    let mut all: DeviceAbSlots = storage::read_json(&path)?;
+    if let Some(slots) = all.remove(from) {
+        all.insert(to.to_string(), slots);
+        storage::write_json(&path, &all)?;
+    }
+    Ok(())
+}
+
+fn no_abx_session() -> AppError {
+    AppError { err_type: ErrorType::BadArguments, message: "No ABX session is running".to_string() }
+}
+
+fn abx_running(state: &AppState) -> bool {
+    lock_abx_idle(state).is_none()
+}
+
+/// Locks the ABX session slot if no session is running. Any write of the real mapping would
+/// replace the trial EQ, so writers hold this until they are done; like the ABX commands, they
+/// take it before the mapping.
+fn lock_abx_idle(state: &AppState) -> Option<MutexGuard<'_, Option<AbxSession>>> {
+    let abx = state.abx.lock().unwrap();
+    if abx.as_ref().is_some_and(|s| !s.is_finished()) {
+        return None;
+    }
+    Some(abx)
+}
+
+fn ensure_no_abx(state: &AppState) -> Result<MutexGuard<'_, Option<AbxSession>>, AppError> {
+    lock_abx_idle(state).ok_or(AppError { err_type: ErrorType::BadArguments, message: "Finish or abort the ABX session first".to_string() })
+}
+
+/// Writes the config with one side of the ABX session swapped in, leaving the stored mapping alone.
+fn play_abx(state: &AppState, session: &AbxSession, source: AbxSource) -> Result<(), AppError> {
+    let mut mapping = state.mapping.lock().unwrap().clone();
+    let bank = mapping.get_mut(&session.device).ok_or_else(|| no_such_section(&session.device))?;
+    *bank = FilterBank { enabled: bank.enabled, inherit_all: bank.inherit_all, direction: bank.direction, ..FilterBank::new(session.eq_for(source)?.clone()) };
+    update_config_file(state, &mapping)
+}
+
+/// Restores the real config and saves the session to the app data directory.
+fn finish_abx(app: &tauri::AppHandle, state: &AppState, session: &AbxSession) -> Result<(), AppError> {
+    update_config_file(state, &state.mapping.lock().unwrap())?;
+    storage::write_json(&app_data_dir(app)?.join(ABX_DIR).join(format!("{}.json", session.id)), session)
+}
+
+fn preset_library(app: &tauri::AppHandle) -> Result<PresetLibrary, AppError> {
+    Ok(PresetLibrary::new(app_data_dir(app)?.join(PRESETS_DIR)))
+}
+
+/// Updates the known-devices registry and re-links sections of devices that were renamed.
+fn sync_known_devices(app: &tauri::AppHandle, state: &AppState, devices: &[DeviceInfo]) -> Result<(), AppError> {
+    // relinks cannot be written during an ABX session; the renames are seen again next time
+    let _abx = match lock_abx_idle(state) {
+        Some(abx) => abx,
+        None => return Ok(()),
+    };
+    let path = app_data_dir(app)?.join(KNOWN_DEVICES_FILE);
+    let mut known: KnownDevices = storage::read_json(&path)?;
+    let renames = known.observe(devices, storage::now());
+    storage::write_json(&path, &known)?;
+    if renames.is_empty() {
+        return Ok(());
+    }
+    for rename in &renames {
+        info!("device {} was renamed: {} -> {}", rename.guid, rename.old_name, rename.new_name);
+    }
+    let (relinks, skipped) = known_devices::relink_changes(&state.mapping.lock().unwrap(), &renames);
+    if !skipped.is_empty() {
+        for relink in &skipped {
+            warn!("not relinking {} to {}, that section already exists", relink.from, relink.to);
+        }
+        if let Err(e) = app.emit_all(RELINK_SKIPPED_EVENT, &skipped) {
+            warn!("Could not notify frontend of skipped relinks: {}", e);
+        }
+    }
+    if !relinks.is_empty() {
+        // not an edit of the user's, so there is nothing to undo
+        let mut mappings = state.mapping.lock().unwrap();
+        let updated = changes::apply_changes(&mappings, &relinks)?;
+        update_config_file(state, &updated)?;
+        *mappings = updated;
+        for relink in &relinks {
+            if let Change::RenameDevice { from, to } = relink {
+                rename_ab_slots(app, from, to)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keeps known devices in sync and forwards the events to the frontend, one `device-event` per
+/// change followed by a single `devices-changed` to re-query the devices.
+fn on_devices_changed(app: &tauri::AppHandle, events: &[DeviceEvent]) {
+    info!("audio devices changed: {:?}", events);
+    let state = app.state::<AppState>();
+    match state.backend.enumerate() {
+        Ok(devices) => if let Err(e) = sync_known_devices(app, &state, &devices) {
+            warn!("Could not sync known devices: {}", e);
+        },
+        Err(e) => warn!("Could not enumerate devices: {}", e),
+    }
+    for event in events {
+        if let DeviceEvent::DefaultChanged { direction: Direction::Render, device: Some(device) } = event {
+            if abx_running(&state) {
+                info!("ABX session running, not switching profile for {}", device.name);
+            } else if let Err(e) = switch_profile(app, &state, device) {
+                warn!("Could not switch profile for {}: {}", device.name, e);
+            }
+        }
+        if let Err(e) = app.emit_all(DEVICE_EVENT, event) {
+            warn!("Could not send device event to frontend: {}", e);
+        }
+    }
+    if let Err(e) = app.emit_all(DEVICES_CHANGED_EVENT, ()) {
+        warn!("Could not notify frontend of device change: {}", e);
+    }
+}
+
+/// Applies the switch rule matching the new default device, if any, and records it in the switch
+/// log whether it worked or not.
+fn switch_profile(app: &tauri::AppHandle, state: &AppState, device: &DeviceInfo) -> Result<(), AppError> {
+    let rules: SwitchRules = storage::read_json(&app_data_dir(app)?.join(SWITCH_RULES_FILE))?;
+    let rule = match rules.rule_for(device) {
+        Some(rule) => rule,
+        None => return Ok(()),
+    };
+    info!("default device is now {}, switching to rule {} ({:?} on {})", device.name, rule.name, rule.target, rule.section);
+    let result = {
+        let changes = auto_switch::switch_changes(rule, &state.mapping.lock().unwrap(), &preset_library(app)?);
+        changes.and_then(|changes| commit_changes(state, &changes))
+    };
+    if let Err(e) = &result {
+        warn!("Profile switch {} failed: {}", rule.name, e);
+    }
+
+    let path = app_data_dir(app)?.join(SWITCH_LOG_FILE);
+    let mut log: SwitchLog = storage::read_json(&path)?;
+    log.record(SwitchLogEntry {
+        time: storage::now(),
+        device_guid: device.guid.clone(),
+        device_name: device.name.clone(),
+        rule: rule.name.clone(),
+        target: rule.target.clone(),
+        error: result.as_ref().err().map(|e| e.message.clone()),
+    });
+    storage::write_json(&path, &log)?;
+    if let Err(e) = app.emit_all(PROFILE_SWITCHED_EVENT, log.entries.back()) {
+        warn!("Could not notify frontend of profile switch: {}", e);
+    }
+    result
+}
+
+/// The sample rate the EQ of a section runs at, from the device it applies to.
+fn section_sample_rate(state: &AppState, section: &str) -> f64 {
+    let devices = state.backend.enumerate().unwrap_or_default();
+    selector::device_for_section(section, &devices).map_or(response::DEFAULT_SAMPLE_RATE, DeviceInfo::sample_rate)
+}
+
+fn find_device(state: &AppState, guid: &str) -> Result<DeviceInfo, AppError> {
+    state.backend.enumerate()?
+        .into_iter()
+        .find(|d| d.guid.eq_ignore_ascii_case(guid))
+        .ok_or(AppError { err_type: ErrorType::BadArguments, message: format!("Could not find device with guid {}", guid) })
+}
+
+/// Applies a batch of changes and writes the config file once; nothing is kept if any change fails.
+fn commit_changes(state: &AppState, changes: &[Change]) -> Result<(), AppError> {
+    commit_changes_in_group(state, changes, history::group_for(changes))
+}
+
+/// Like `commit_changes`, but with an explicit undo group for edits driven by a continuous control.
+fn commit_changes_in_group(state: &AppState, changes: &[Change], group: Option<String>) -> Result<(), AppError> {
+    let _abx = ensure_no_abx(state)?;
+    let mut mappings = state.mapping.lock().unwrap();
+    let updated = changes::apply_changes(&mappings, changes)?;
+    commit_mapping(state, &mut mappings, updated, group)
+}
+
+/// Writes the updated mapping and records the replaced one in the undo history.
+fn commit_mapping(state: &AppState, current: &mut DeviceFilterMapping, updated: DeviceFilterMapping, group: Option<String>) -> Result<(), AppError> {
+    update_config_file(state, &updated)?;
+    let previous = std::mem::replace(current, updated);
+    state.history.lock().unwrap().record(previous, group, Instant::now());
     Ok(())
 }
 
@@ -242,13 +917,82 @@ fn show_main_page(state: AppState) {
             add_filter,
             remove_filter,
             modify_preamp,
+            apply_changes,
+            apply_patch,
+            add_layer,
+            remove_layer,
+            rename_layer,
+            set_layer_enabled,
+            set_layer_gain,
+            move_layer,
+            select_layer,
+            undo,
+            redo,
+            end_history_group,
+            add_device_mapping,
+            remove_device_mapping,
+            set_device_enabled,
+            rename_device_mapping,
+            copy_device_eq,
             query_devices,
+            get_apo_status,
+            diagnose_device,
+            get_section_matches,
+            get_known_devices,
+            get_switch_rules,
+            set_switch_rules,
+            get_switch_log,
+            set_inherit_all,
+            get_effective_chain,
+            save_preset,
+            get_preset,
+            list_presets,
+            rename_preset,
+            delete_preset,
+            apply_preset,
+            import_autoeq,
+            export_autoeq,
+            import_rew,
+            export_rew,
+            export_eq,
+            get_peace_status,
+            import_peace,
+            set_peace_include_enabled,
+            get_ab_slots,
+            set_ab_slot,
+            clear_ab_slot,
+            get_ab_loudness_offsets,
+            switch_ab_slot,
+            start_abx,
+            get_abx_status,
+            abx_play,
+            abx_answer,
+            abort_abx,
+            list_abx_sessions,
+            morph_eq,
+            set_eq_depth,
             log_bridge,
             quit,
         ])
         .build(tauri::generate_context!())
         .expect("error while running tauri application");
 
+    let state = app.state::<AppState>();
+    match state.backend.enumerate() {
+        Ok(devices) => if let Err(e) = sync_known_devices(&app.handle(), &state, &devices) {
+            warn!("Could not sync known devices: {}", e);
+        },
+        Err(e) => warn!("Could not enumerate devices: {}", e),
+    }
+    let handle = app.handle();
+    let mut watcher = match state.backend.clone().watch(Box::new(move |events| on_devices_changed(&handle, events))) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Could not watch for device changes: {}", e);
+            None
+        },
+    };
+
     tauri::WindowBuilder::new(
         &app,
         "main",
@@ -261,13 +1005,22 @@ fn show_main_page(state: AppState) {
         .expect("failed to build window")
         .show()
         .expect("failed to show window");
-    app.run(|_, _| {});
+    app.run(move |_, event| {
+        if let tauri::RunEvent::Exit = event {
+            watcher.take();
+        }
+    });
 }
 
 fn main() {
     env_logger::init();
 
-    let state = AppState::default();
+    let backend = match backend::select() {
+        Ok(backend) => backend,
+        Err(e) => return show_error_page(e),
+    };
+    info!("using {} audio backend", backend.name());
+    let state = AppState::new(backend);
 
     match initialize(&state) {
         Err(e) => show_error_page(e),
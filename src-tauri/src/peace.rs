@@ -0,0 +1,366 @@
+//! Migration from Peace, the other popular EqualizerAPO front end.
+//!
+//! Peace stores presets as INI-style `.peace` files:
+//!
+//! ```ini
+//! [General]
+//! PreAmp=-4
+//! [Speakers]
+//! SpeakerId0=0
+//! SpeakerName0=All
+//! SpeakerId1=1
+//! SpeakerName1=Headphones
+//! [Filters]
+//! Filter0=1
+//! Frequency0=105
+//! Gain0=5.5
+//! Quality0=0.7
+//! SpeakerId0=0
+//! ```
+//!
+//! Every "speaker" becomes a device mapping: `All` goes to the `all` section and named speakers
+//! go to the section of the connected device they name (see `resolve_devices`). Peace speakers that
+//! are single channels have no eq+ equivalent and are reported back instead of imported.
+//!
+//! Peace also writes its active configuration to `peace.txt` and includes it from config.txt. When
+//! both tools are active the two EQs stack, so the include can be commented out (and restored).
+
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::backend::{DeviceInfo, Direction};
+use crate::errors::AppError;
+use crate::filters::{at_line, EqState, FilterParams, FilterType, ALL_DEVICES};
+use crate::selector::DeviceSelector;
+
+pub const PEACE_CONFIG: &str = "peace.txt";
+/// Marks includes eq+ commented out, so only those are restored.
+const DISABLED_MARKER: &str = "# eq+ disabled: ";
+
+const CHANNEL_NAMES: [&str; 16] = [
+    "L", "R", "C", "LFE", "RL", "RR", "RC", "SL", "SR",
+    "Left", "Right", "Center", "Subwoofer", "Rear left", "Rear right", "Rear center",
+];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PeaceImport {
+    /// Section name to EQ.
+    pub banks: BTreeMap<String, EqState>,
+    /// Human readable notes about everything that could not be carried over as it was.
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PeaceStatus {
+    /// Peace's configuration exists in the config directory.
+    pub installed: bool,
+    /// config.txt currently includes it.
+    pub included: bool,
+    /// eq+ commented the include out earlier.
+    pub disabled_by_eqplus: bool,
+}
+
+#[derive(Default)]
+struct PeaceFilter {
+    filter_type: Option<i64>,
+    frequency: Option<f64>,
+    gain: Option<f64>,
+    q: Option<f64>,
+    speaker: i64,
+    line: usize,
+}
+
+pub fn import(contents: &str) -> Result<PeaceImport, AppError> {
+    let mut preamp = 0.0;
+    let mut speakers: BTreeMap<i64, (String, f64)> = BTreeMap::new();
+    let mut speaker_names: BTreeMap<String, String> = BTreeMap::new();
+    let mut speaker_ids: BTreeMap<String, i64> = BTreeMap::new();
+    let mut speaker_preamps: BTreeMap<String, f64> = BTreeMap::new();
+    let mut filters: BTreeMap<String, PeaceFilter> = BTreeMap::new();
+    let mut section = String::new();
+
+    for (i, raw_line) in contents.lines().enumerate() {
+        let number = i + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_lowercase();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .map(|(k, v)| (k.trim().to_lowercase(), v.trim()))
+            .ok_or_else(|| at_line(number, AppError::invalid_config(format!("Expected key=value: {}", line))))?;
+        let (name, index) = split_index(&key);
+        match (section.as_str(), name) {
+            ("general", "preamp") => preamp = number_value(value, line, number)?,
+            ("speakers", "speakerid") => { speaker_ids.insert(index.to_string(), number_value(value, line, number)? as i64); },
+            ("speakers", "speakername") => { speaker_names.insert(index.to_string(), value.to_string()); },
+            ("speakers", "speakerpreamp") => { speaker_preamps.insert(index.to_string(), number_value(value, line, number)?); },
+            ("filters", "filter" | "frequency" | "gain" | "quality" | "speakerid") => {
+                let filter = filters.entry(index.to_string()).or_insert_with(|| PeaceFilter { line: number, ..Default::default() });
+                let value = number_value(value, line, number)?;
+                match name {
+                    "filter" => filter.filter_type = Some(value as i64),
+                    "frequency" => filter.frequency = Some(value),
+                    "gain" => filter.gain = Some(value),
+                    "quality" => filter.q = Some(value),
+                    _ => filter.speaker = value as i64,
+                }
+            },
+            // window positions, graph settings, hotkeys and the like
+            _ => {},
+        }
+    }
+
+    for (index, name) in speaker_names {
+        let id = speaker_ids.get(&index).cloned().unwrap_or(index.parse().unwrap_or(0));
+        speakers.insert(id, (name, speaker_preamps.get(&index).cloned().unwrap_or(0.0)));
+    }
+    speakers.entry(0).or_insert(("All".to_string(), 0.0));
+
+    let mut result = PeaceImport { banks: BTreeMap::new(), skipped: vec![] };
+    let mut ordered: Vec<PeaceFilter> = filters.into_values().collect();
+    ordered.sort_by_key(|f| f.line);
+    for filter in ordered {
+        let (speaker, _) = speakers
+            .get(&filter.speaker)
+            .ok_or_else(|| at_line(filter.line, AppError::invalid_config(format!("Filter refers to unknown speaker {}", filter.speaker))))?;
+        if is_channel(speaker) {
+            continue;
+        }
+        let params = match to_filter_params(&filter)? {
+            Some(params) => params,
+            None => continue,
+        };
+        let eq = result.banks.entry(section_for(speaker)).or_insert_with(empty_eq);
+        eq.filters.push(FilterParams { id: (eq.filters.len() + 1).to_string(), ..params });
+    }
+    for (speaker, speaker_preamp) in speakers.values() {
+        if is_channel(speaker) {
+            result.skipped.push(format!("Speaker \"{}\" is a single channel; per-channel EQ is not supported", speaker));
+            continue;
+        }
+        let eq = result.banks.entry(section_for(speaker)).or_insert_with(empty_eq);
+        eq.preamp += speaker_preamp;
+    }
+    result.banks.entry(ALL_DEVICES.to_string()).or_insert_with(empty_eq).preamp += preamp;
+    result.banks.retain(|section, eq| section == ALL_DEVICES || !eq.filters.is_empty() || eq.preamp != 0.0);
+    Ok(result)
+}
+
+/// Moves the banks of named speakers to the section of the output device they name. Used as is,
+/// a speaker name would be a bare `Device:` pattern matching any device whose name contains it.
+/// Names matching no device, or more than one, stay patterns and are noted in `skipped`.
+pub fn resolve_devices(imported: &mut PeaceImport, devices: &[DeviceInfo]) {
+    let outputs: Vec<&DeviceInfo> = devices.iter().filter(|d| d.direction == Direction::Render).collect();
+    let speakers: Vec<String> = imported.banks.keys().filter(|s| s.as_str() != ALL_DEVICES).cloned().collect();
+    for speaker in speakers {
+        let named: Vec<&&DeviceInfo> = outputs.iter().filter(|d| d.name.eq_ignore_ascii_case(&speaker)).collect();
+        let matched: Vec<&&DeviceInfo> = if named.is_empty() {
+            let selector = DeviceSelector::parse(&speaker);
+            outputs.iter().filter(|d| selector.matches_device(d)).collect()
+        } else {
+            named
+        };
+        let note = match matched[..] {
+            [device] => {
+                let section = DeviceSelector::for_device(device).to_string();
+                if !imported.banks.contains_key(&section) {
+                    let eq = imported.banks.remove(&speaker).unwrap();
+                    imported.banks.insert(section, eq);
+                    continue;
+                }
+                format!("Speaker \"{}\" names {}, which another speaker already went to; kept as a device pattern", speaker, device.name)
+            },
+            [] => format!("Speaker \"{}\" matches no connected output device; kept as a device pattern", speaker),
+            _ => format!("Speaker \"{}\" matches {} output devices; kept as a device pattern", speaker, matched.len()),
+        };
+        warn!("{}", note);
+        imported.skipped.push(note);
+    }
+}
+
+/// Whether config.txt has an active include of Peace's configuration.
+pub fn is_included(config: &str) -> bool {
+    config.lines().any(is_peace_include)
+}
+
+/// Comments out every active Peace include, marking it so `enable_include` can restore it.
+pub fn disable_include(config: &str) -> String {
+    map_lines(config, |line| {
+        if is_peace_include(line) { format!("{}{}", DISABLED_MARKER, line.trim()) } else { line.to_string() }
+    })
+}
+
+/// Restores the includes `disable_include` commented out.
+pub fn enable_include(config: &str) -> String {
+    map_lines(config, |line| match line.trim().strip_prefix(DISABLED_MARKER) {
+        Some(include) => include.to_string(),
+        None => line.to_string(),
+    })
+}
+
+pub fn status(config: &str, installed: bool) -> PeaceStatus {
+    PeaceStatus {
+        installed,
+        included: is_included(config),
+        disabled_by_eqplus: config.lines().any(|l| l.trim().starts_with(DISABLED_MARKER)),
+    }
+}
+
+fn is_peace_include(line: &str) -> bool {
+    let line = line.trim();
+    match line.strip_prefix("Include:") {
+        Some(path) => {
+            let path = path.trim().to_lowercase();
+            path.rsplit(['\\', '/']).next() == Some(PEACE_CONFIG)
+        },
+        None => false,
+    }
+}
+
+fn map_lines(config: &str, f: impl Fn(&str) -> String) -> String {
+    let mut mapped: Vec<String> = config.lines().map(f).collect();
+    if config.ends_with('\n') {
+        mapped.push(String::new());
+    }
+    mapped.join("\n")
+}
+
+fn to_filter_params(filter: &PeaceFilter) -> Result<Option<FilterParams>, AppError> {
+    let filter_type = match filter.filter_type.unwrap_or(1) {
+        0 => return Ok(None),
+        1 => FilterType::Peaking,
+        2 => FilterType::LowPass,
+        3 => FilterType::HighPass,
+        4 => FilterType::BandPass,
+        5 => FilterType::LowShelf,
+        6 => FilterType::HighShelf,
+        7 => FilterType::Notch,
+        8 => FilterType::AllPass,
+        other => return Err(at_line(filter.line, AppError::invalid_config(format!("Unsupported filter type: {}", other)))),
+    };
+    let frequency = filter.frequency.ok_or_else(|| at_line(filter.line, AppError::invalid_config("Filter is missing its frequency".to_string())))?;
+    let q = filter.q.unwrap_or(std::f64::consts::SQRT_2);
+    if frequency <= 0.0 || q <= 0.0 {
+        return Err(at_line(filter.line, AppError::invalid_config("Frequency and Q must be greater than zero".to_string())));
+    }
+    Ok(Some(FilterParams { id: String::new(), frequency, gain: filter.gain.unwrap_or(0.0), q, filter_type }))
+}
+
+/// `frequency12` -> (`frequency`, `12`)
+fn split_index(key: &str) -> (&str, &str) {
+    let split = key.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    key.split_at(split)
+}
+
+fn number_value(value: &str, line: &str, number: usize) -> Result<f64, AppError> {
+    value
+        .replace(',', ".")
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| at_line(number, AppError::invalid_config(format!("Invalid number: {}", line))))
+}
+
+fn is_channel(speaker: &str) -> bool {
+    CHANNEL_NAMES.iter().any(|c| c.eq_ignore_ascii_case(speaker.trim()))
+}
+
+fn section_for(speaker: &str) -> String {
+    if speaker.trim().eq_ignore_ascii_case("all") {
+        ALL_DEVICES.to_string()
+    } else {
+        speaker.trim().to_string()
+    }
+}
+
+fn empty_eq() -> EqState {
+    EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] }
+}
+
+
+#[test]
+fn test_peace_import() {
+    let preset = "[General]\n\
+        PreAmp=-4\n\
+        [Speakers]\n\
+        SpeakerId0=0\n\
+        SpeakerName0=All\n\
+        SpeakerId1=1\n\
+        SpeakerName1=Headphones\n\
+        SpeakerPreAmp1=-1,5\n\
+        SpeakerId2=2\n\
+        SpeakerName2=Left\n\
+        [Filters]\n\
+        Filter0=5\n\
+        Frequency0=105\n\
+        Gain0=5.5\n\
+        Quality0=0.7\n\
+        Filter1=1\n\
+        Frequency1=3000\n\
+        Gain1=-2\n\
+        Quality1=2\n\
+        SpeakerId1=1\n\
+        Filter2=1\n\
+        Frequency2=1000\n\
+        SpeakerId2=2\n\
+        Filter3=0\n\
+        Frequency3=50\n";
+    let imported = import(preset).unwrap();
+    assert_eq!(imported.banks.len(), 2);
+    let all = &imported.banks[ALL_DEVICES];
+    assert_eq!(all.preamp, -4.0);
+    assert_eq!(all.filters.len(), 1);
+    assert!(matches!(all.filters[0].filter_type, FilterType::LowShelf));
+    let headphones = &imported.banks["Headphones"];
+    assert_eq!(headphones.preamp, -1.5);
+    assert_eq!(headphones.filters[0].frequency, 3000.0);
+    assert_eq!(headphones.filters[0].id, "1");
+    assert_eq!(imported.skipped.len(), 1);
+
+    let e = import("[Filters]\nFilter0=1\nFrequency0=abc\n").unwrap_err();
+    assert_eq!(e.message, "Line 3: Invalid number: Frequency0=abc");
+    assert!(import("[Filters]\nFilter0=42\nFrequency0=100\n").is_err());
+}
+
+#[test]
+fn test_resolve_devices() {
+    let device = |guid: &str, name: &str, direction: Direction| DeviceInfo { guid: guid.to_string(), name: name.to_string(), apo_installed: true, is_default: false, direction, format: None };
+    let devices = vec![
+        device("{0001}", "Headphones (USB DAC)", Direction::Render),
+        device("{0002}", "Headphones (Realtek Audio)", Direction::Render),
+        device("{0003}", "Speakers (Realtek Audio)", Direction::Render),
+        device("{0004}", "Microphone (USB DAC)", Direction::Capture),
+    ];
+    let mut imported = PeaceImport { banks: BTreeMap::new(), skipped: vec![] };
+    for speaker in [ALL_DEVICES, "Speakers", "headphones (usb dac)", "Headphones", "USB DAC", "HDMI"] {
+        imported.banks.insert(speaker.to_string(), empty_eq());
+    }
+    resolve_devices(&mut imported, &devices);
+
+    let sections: Vec<&str> = imported.banks.keys().map(String::as_str).collect();
+    // "USB DAC" gets the DAC's section first, so the exact name has to stay a pattern
+    assert_eq!(sections, vec!["HDMI", "Headphones", "Headphones USB DAC {0001}", "Speakers Realtek Audio {0003}", ALL_DEVICES, "headphones (usb dac)"]);
+    assert_eq!(imported.skipped.len(), 3);
+    assert!(imported.skipped[0].contains("matches no connected output device"));
+    assert!(imported.skipped[1].contains("matches 2 output devices"));
+    assert!(imported.skipped[2].contains("already went to"));
+}
+
+#[test]
+fn test_peace_include() {
+    let config = "Device: all\nInclude: eqplus.txt\nInclude: C:\\Program Files\\EqualizerAPO\\config\\Peace.txt\n";
+    assert!(is_included(config));
+    let disabled = disable_include(config);
+    assert!(!is_included(&disabled));
+    assert!(disabled.contains("Include: eqplus.txt\n# eq+ disabled: Include: C:\\"));
+    assert!(status(&disabled, true).disabled_by_eqplus);
+    assert_eq!(enable_include(&disabled), config);
+    assert!(!is_included("# Include: peace.txt\nInclude: notpeace.txt"));
+}
@@ -0,0 +1,197 @@
+//! Sink and source discovery from `pw-dump` output.
+
+use serde_json::Value;
+
+use crate::backend::{self, DeviceInfo, Direction, MixFormat};
+use crate::errors::{AppError, ErrorType};
+
+use super::FILTER_NODE_PREFIX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub node_name: String,
+    pub direction: Direction,
+    pub description: String,
+    pub channels: u32,
+    pub positions: Vec<String>,
+    /// Only known once the node has negotiated a format, or when its config pins one.
+    pub rate: Option<u32>,
+    pub sample_format: Option<String>,
+}
+
+impl Node {
+    /// PipeWire has no endpoint GUIDs; the node name is stable across restarts, so it stands in
+    /// for one (braced, so selectors treat it like a Windows endpoint GUID).
+    pub fn guid(&self) -> String {
+        format!("{{{}}}", self.node_name)
+    }
+
+    pub fn mix_format(&self) -> Option<MixFormat> {
+        let positions: Vec<&str> = self.positions.iter().map(|p| speaker_position(p)).collect();
+        Some(MixFormat {
+            sample_rate: self.rate?,
+            // the graph itself always runs in 32 bit float
+            bit_depth: self.sample_format.as_deref().and_then(bit_depth).unwrap_or(32),
+            channels: self.channels as u16,
+            channel_mask: backend::channel_mask(&positions),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PwState {
+    pub nodes: Vec<Node>,
+    pub default_sink: Option<String>,
+    pub default_source: Option<String>,
+}
+
+impl PwState {
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.nodes
+            .iter()
+            .map(|node| DeviceInfo {
+                guid: node.guid(),
+                name: node.description.clone(),
+                // the filter chain works in front of any sink and behind any source
+                apo_installed: true,
+                is_default: self.default_node(node.direction) == Some(node.node_name.as_str()),
+                direction: node.direction,
+                format: node.mix_format(),
+            })
+            .collect()
+    }
+
+    pub fn default_node(&self, direction: Direction) -> Option<&str> {
+        match direction {
+            Direction::Render => self.default_sink.as_deref(),
+            Direction::Capture => self.default_source.as_deref(),
+        }
+    }
+}
+
+/// Parses the JSON array `pw-dump` prints. Our own filter-chain nodes are left out.
+pub fn parse(json: &str) -> Result<PwState, AppError> {
+    let objects: Vec<Value> = serde_json::from_str(json).map_err(|e| AppError {
+        err_type: ErrorType::GenericIoError,
+        message: format!("Could not parse pw-dump output: {}", e)
+    })?;
+    let mut state = PwState { nodes: vec![], default_sink: None, default_source: None };
+    for object in &objects {
+        match object["type"].as_str() {
+            Some("PipeWire:Interface:Node") => {
+                let props = &object["info"]["props"];
+                let direction = match props["media.class"].as_str() {
+                    Some("Audio/Sink") => Direction::Render,
+                    Some("Audio/Source") => Direction::Capture,
+                    _ => continue,
+                };
+                let node_name = match props["node.name"].as_str() {
+                    Some(name) if !name.starts_with(FILTER_NODE_PREFIX) => name.to_string(),
+                    _ => continue,
+                };
+                let description = props["node.description"]
+                    .as_str()
+                    .or(props["node.nick"].as_str())
+                    .unwrap_or(&node_name)
+                    .to_string();
+                let positions: Vec<String> = props["audio.position"]
+                    .as_str()
+                    .map(|p| p.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect())
+                    .unwrap_or_default();
+                // the negotiated format wins over what the props ask for
+                let format = &object["info"]["params"]["Format"][0];
+                let positions: Vec<String> = format["position"]
+                    .as_array()
+                    .map(|p| p.iter().filter_map(Value::as_str).map(str::to_string).collect())
+                    .unwrap_or(positions);
+                let channels = format["channels"]
+                    .as_u64()
+                    .or(props["audio.channels"].as_u64())
+                    .map(|c| c as u32)
+                    .unwrap_or(if positions.is_empty() { 2 } else { positions.len() as u32 });
+                let rate = format["rate"].as_u64().or(props["audio.rate"].as_u64()).map(|r| r as u32);
+                let sample_format = format["format"].as_str().or(props["audio.format"].as_str()).map(str::to_string);
+                state.nodes.push(Node { node_name, direction, description, channels, positions, rate, sample_format });
+            },
+            Some("PipeWire:Interface:Metadata") if object["props"]["metadata.name"].as_str() == Some("default") => {
+                let entries = object["metadata"].as_array().cloned().unwrap_or_default();
+                state.default_sink = default_name(&entries, "default.audio.sink");
+                state.default_source = default_name(&entries, "default.audio.source");
+            },
+            _ => {},
+        }
+    }
+    Ok(state)
+}
+
+/// PipeWire's channel names, in the spelling of `SPEAKER_POSITIONS`.
+fn speaker_position(position: &str) -> &str {
+    match position {
+        "MONO" => "FC",
+        "RL" => "BL",
+        "RR" => "BR",
+        "RC" => "BC",
+        "TRL" => "TBL",
+        "TRC" => "TBC",
+        "TRR" => "TBR",
+        other => other,
+    }
+}
+
+/// Bits per sample of an SPA format name like `S16LE`, `S24_32LE` or `F32P`.
+fn bit_depth(format: &str) -> Option<u16> {
+    let digits: String = format.chars().skip(1).take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+fn default_name(entries: &[Value], key: &str) -> Option<String> {
+    let entry = entries.iter().find(|e| e["key"].as_str() == Some(key))?;
+    // the value is usually an object, but older versions store it as a JSON string
+    let value = match &entry["value"] {
+        Value::String(raw) => serde_json::from_str(raw).unwrap_or(Value::Null),
+        value => value.clone(),
+    };
+    value["name"].as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+pub const TEST_DUMP: &str = r#"[
+    { "id": 0, "type": "PipeWire:Interface:Core", "info": { "name": "pipewire-0" } },
+    { "id": 40, "type": "PipeWire:Interface:Metadata", "props": { "metadata.name": "default" },
+      "metadata": [ { "subject": 0, "key": "default.audio.sink", "type": "Spa:String:JSON", "value": { "name": "alsa_output.usb-dac.analog-stereo" } },
+                    { "subject": 0, "key": "default.audio.source", "type": "Spa:String:JSON", "value": "{\"name\":\"alsa_input.usb-dac.mono\"}" } ] },
+    { "id": 51, "type": "PipeWire:Interface:Node", "info": { "props": {
+        "node.name": "alsa_output.pci-0000_00_1f.3.analog-stereo", "node.description": "Built-in Audio Analog Stereo",
+        "media.class": "Audio/Sink", "audio.channels": 2, "audio.position": "FL,FR" },
+      "params": { "Format": [ { "mediaType": "audio", "mediaSubtype": "raw", "format": "S24_32LE", "rate": 44100, "channels": 2, "position": [ "FL", "FR" ] } ] } } },
+    { "id": 52, "type": "PipeWire:Interface:Node", "info": { "props": {
+        "node.name": "alsa_output.usb-dac.analog-stereo", "node.nick": "USB DAC", "media.class": "Audio/Sink" } } },
+    { "id": 53, "type": "PipeWire:Interface:Node", "info": { "props": {
+        "node.name": "alsa_input.usb-dac.mono", "node.description": "USB DAC Mic", "media.class": "Audio/Source", "audio.channels": 1, "audio.position": "MONO", "audio.rate": 16000 } } },
+    { "id": 60, "type": "PipeWire:Interface:Node", "info": { "props": {
+        "node.name": "eqplus.alsa_output.usb-dac.analog-stereo", "node.description": "eq+ USB DAC", "media.class": "Audio/Sink" } } },
+    { "id": 61, "type": "PipeWire:Interface:Node", "info": { "props": {
+        "node.name": "eqplus.alsa_input.usb-dac.mono", "node.description": "eq+ USB DAC Mic", "media.class": "Audio/Source" } } }
+]"#;
+
+#[test]
+fn test_parse_pw_dump() {
+    let state = parse(TEST_DUMP).unwrap();
+    assert_eq!(state.nodes.len(), 3);
+    assert_eq!(state.nodes[0].positions, vec!["FL", "FR"]);
+    assert_eq!(state.nodes[1].description, "USB DAC");
+    assert_eq!(state.nodes[1].channels, 2);
+    assert_eq!(state.nodes[2].direction, Direction::Capture);
+    assert_eq!(state.nodes[2].channels, 1);
+
+    let devices = state.devices();
+    assert_eq!(devices[1].guid, "{alsa_output.usb-dac.analog-stereo}");
+    assert!(!devices[0].is_default && devices[1].is_default);
+    // the default source is stored as a JSON string here
+    assert!(devices[2].is_default);
+
+    assert_eq!(devices[0].format, Some(MixFormat { sample_rate: 44100, bit_depth: 24, channels: 2, channel_mask: 0x3 }));
+    assert_eq!(devices[1].format, None);
+    assert_eq!(devices[2].format, Some(MixFormat { sample_rate: 16000, bit_depth: 32, channels: 1, channel_mask: 0x4 }));
+    assert!(parse("{}").is_err());
+}
@@ -0,0 +1,134 @@
+//! Renders effective EQ chains as PipeWire filter-chain modules.
+//!
+//! Every sink with an EQ gets its own virtual sink: a series of builtin biquad nodes (the preamp
+//! is a high shelf at 0 Hz) whose output plays into the real sink. A source gets a virtual source
+//! the other way around, fed from the real one. PipeWire duplicates mono graphs for every channel,
+//! so one chain covers all of them.
+
+use log::warn;
+
+use crate::backend::Direction;
+use crate::export::pipewire_type;
+use crate::filters::{trim_float, EqState, FilterType};
+
+use super::dump::Node;
+use super::FILTER_NODE_PREFIX;
+
+/// A whole `filter-chain.conf.d` fragment for the given nodes and their chains.
+pub fn render(chains: &[(&Node, EqState)]) -> String {
+    let modules: Vec<String> = chains.iter().map(|(node, eq)| module(node, eq)).collect();
+    format!(
+        "# Generated by eq+, changes will be overwritten.\ncontext.modules = [\n{}]\n",
+        modules.join("")
+    )
+}
+
+fn module(device: &Node, eq: &EqState) -> String {
+    if !eq.graphic_eq.is_empty() {
+        warn!("GraphicEQ curves can not be expressed as biquads and are left out for {}", device.node_name);
+    }
+    let mut nodes = vec![node("preamp", FilterType::HighShelf, 0.0, 1.0, eq.preamp)];
+    for (i, filter) in eq.filters.iter().enumerate() {
+        nodes.push(node(&format!("filter_{}", i + 1), filter.filter_type, filter.frequency, filter.q, filter.gain));
+    }
+    let mut names = vec!["preamp".to_string()];
+    names.extend((1..=eq.filters.len()).map(|i| format!("filter_{}", i)));
+    let links: Vec<String> = names
+        .windows(2)
+        .map(|pair| format!("                    {{ output = \"{}:Out\" input = \"{}:In\" }}\n", pair[0], pair[1]))
+        .collect();
+    let positions = if device.positions.is_empty() { "FL FR".to_string() } else { device.positions.join(" ") };
+    let description = quote(&format!("eq+ {}", device.description));
+    let virtual_name = format!("{}{}", FILTER_NODE_PREFIX, device.node_name);
+    // the virtual node takes the real one's place: a sink we capture from, a source we play into
+    let (capture, playback) = match device.direction {
+        Direction::Render => (
+            class_props(&virtual_name, "Audio/Sink"),
+            target_props(&format!("{}output.{}", FILTER_NODE_PREFIX, device.node_name), &device.node_name),
+        ),
+        Direction::Capture => (
+            target_props(&format!("{}capture.{}", FILTER_NODE_PREFIX, device.node_name), &device.node_name),
+            class_props(&virtual_name, "Audio/Source"),
+        ),
+    };
+
+    format!(
+r#"    {{ name = libpipewire-module-filter-chain
+        args = {{
+            node.description = {description}
+            media.name = {description}
+            filter.graph = {{
+                nodes = [
+{nodes}                ]
+                links = [
+{links}                ]
+            }}
+            audio.channels = {channels}
+            audio.position = [ {positions} ]
+            capture.props = {{
+                {capture}
+            }}
+            playback.props = {{
+                {playback}
+            }}
+        }}
+    }}
+"#,
+        description = description,
+        nodes = nodes.concat(),
+        links = links.concat(),
+        channels = device.channels,
+        positions = positions,
+        capture = capture,
+        playback = playback,
+    )
+}
+
+fn node(name: &str, filter_type: FilterType, frequency: f64, q: f64, gain: f64) -> String {
+    format!(
+        "                    {{ type = builtin name = {} label = {} control = {{ \"Freq\" = {} \"Q\" = {} \"Gain\" = {} }} }}\n",
+        name, pipewire_type(filter_type), trim_float(frequency, 3), trim_float(q, 3), trim_float(gain, 3)
+    )
+}
+
+fn class_props(name: &str, media_class: &str) -> String {
+    format!("node.name = {}\n                media.class = {}", quote(name), media_class)
+}
+
+fn target_props(name: &str, target: &str) -> String {
+    format!("node.name = {}\n                node.passive = true\n                target.object = {}", quote(name), quote(target))
+}
+
+/// SPA JSON string literal.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[test]
+fn test_render_filter_chain() {
+    use crate::filters::FilterParams;
+
+    let sink = Node { node_name: "alsa_output.usb".to_string(), direction: Direction::Render, description: "USB \"DAC\"".to_string(), channels: 2, positions: vec![], rate: None, sample_format: None };
+    let eq = EqState {
+        preamp: -3.0,
+        filters: vec![
+            FilterParams { id: "1".to_string(), frequency: 105.0, gain: 5.5, q: 0.7, filter_type: FilterType::LowShelf },
+            FilterParams { id: "2".to_string(), frequency: 3000.0, gain: -2.0, q: 1.41, filter_type: FilterType::Peaking },
+        ],
+        graphic_eq: vec![],
+    };
+    let conf = render(&[(&sink, eq)]);
+    assert!(conf.contains("{ type = builtin name = preamp label = bq_highshelf control = { \"Freq\" = 0 \"Q\" = 1 \"Gain\" = -3 } }"));
+    assert!(conf.contains("name = filter_1 label = bq_lowshelf control = { \"Freq\" = 105 \"Q\" = 0.7 \"Gain\" = 5.5 }"));
+    assert!(conf.contains("{ output = \"preamp:Out\" input = \"filter_1:In\" }\n                    { output = \"filter_1:Out\" input = \"filter_2:In\" }\n"));
+    assert!(conf.contains("node.description = \"eq+ USB \\\"DAC\\\"\""));
+    assert!(conf.contains("audio.position = [ FL FR ]"));
+    assert!(conf.contains("node.name = \"eqplus.alsa_output.usb\""));
+    assert!(conf.contains("target.object = \"alsa_output.usb\""));
+
+    let source = Node { node_name: "alsa_input.usb".to_string(), direction: Direction::Capture, description: "USB Mic".to_string(), channels: 1, positions: vec!["MONO".to_string()], rate: Some(16000), sample_format: None };
+    let conf = render(&[(&source, EqState::default())]);
+    assert!(conf.contains("capture.props = {\n                node.name = \"eqplus.capture.alsa_input.usb\"\n                node.passive = true\n                target.object = \"alsa_input.usb\"\n"));
+    assert!(conf.contains("playback.props = {\n                node.name = \"eqplus.alsa_input.usb\"\n                media.class = Audio/Source\n"));
+    assert_eq!(render(&[]), "# Generated by eq+, changes will be overwritten.\ncontext.modules = [\n]\n");
+}
@@ -0,0 +1,211 @@
+//! Linux backend: EQ through PipeWire's filter-chain module.
+//!
+//! eq+ keeps its own config directory (config.txt and eqplus.txt, same as under EqualizerAPO) and
+//! renders the effective chain of every sink and source into `filter-chain.conf.d`. The chains run in
+//! PipeWire's separate filter-chain service, which only reads its config on startup, so every change
+//! restarts that service instead of the whole daemon.
+
+pub mod dump;
+pub mod filter_chain;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+use log::info;
+
+use crate::backend::{AudioBackend, DeviceInfo};
+use crate::errors::{AppError, ErrorType};
+use crate::filters::DeviceFilterMapping;
+use crate::selector;
+
+/// Prefix of the node names of our own filter-chain sinks and sources.
+pub const FILTER_NODE_PREFIX: &str = "eqplus.";
+const CONF_FILE: &str = "eqplus.conf";
+
+/// Produces `pw-dump` output; swapped out in tests.
+pub type DumpSource = Box<dyn Fn() -> Result<String, AppError> + Send + Sync>;
+/// Restarts the filter-chain service after its config changed; swapped out in tests.
+pub type ReloadHook = Box<dyn Fn() -> Result<(), AppError> + Send + Sync>;
+
+pub struct PipeWireBackend {
+    config_dir: PathBuf,
+    conf_dir: PathBuf,
+    dump: DumpSource,
+    reload: ReloadHook,
+    /// The nodes of the last `pw-dump`, kept current by the device watcher, so edits render
+    /// against them instead of running it again.
+    last_state: Mutex<Option<dump::PwState>>,
+}
+
+impl PipeWireBackend {
+    /// Uses `$XDG_CONFIG_HOME` (or `~/.config`), the real `pw-dump`, which has to work, and
+    /// `systemctl --user` to restart the filter-chain service.
+    pub fn new() -> Result<PipeWireBackend, AppError> {
+        let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".config"))
+                .ok_or(AppError { err_type: ErrorType::InvalidConfigDirectory, message: "Neither XDG_CONFIG_HOME nor HOME is set".to_string() })?,
+        };
+        let backend = PipeWireBackend::with_commands(
+            base.join("eqplus"),
+            base.join("pipewire").join("filter-chain.conf.d"),
+            Box::new(run_pw_dump),
+            Box::new(restart_filter_chain),
+        );
+        backend.state()?;
+        Ok(backend)
+    }
+
+    pub fn with_commands(config_dir: PathBuf, conf_dir: PathBuf, dump: DumpSource, reload: ReloadHook) -> PipeWireBackend {
+        PipeWireBackend { config_dir, conf_dir, dump, reload, last_state: Mutex::new(None) }
+    }
+
+    fn state(&self) -> Result<dump::PwState, AppError> {
+        let state = dump::parse(&(self.dump)()?)?;
+        *self.last_state.lock().unwrap() = Some(state.clone());
+        Ok(state)
+    }
+
+    fn known_state(&self) -> Result<dump::PwState, AppError> {
+        let last = self.last_state.lock().unwrap().clone();
+        match last {
+            Some(state) => Ok(state),
+            None => self.state(),
+        }
+    }
+}
+
+impl AudioBackend for PipeWireBackend {
+    fn name(&self) -> &'static str {
+        "pipewire"
+    }
+
+    fn config_dir(&self) -> Result<String, AppError> {
+        fs::create_dir_all(&self.config_dir)?;
+        let config = self.config_dir.join("config.txt");
+        if !config.exists() {
+            fs::write(&config, "")?;
+        }
+        Ok(self.config_dir.to_string_lossy().to_string())
+    }
+
+    fn enumerate(&self) -> Result<Vec<DeviceInfo>, AppError> {
+        Ok(self.state()?.devices())
+    }
+
+    fn apply(&self, mapping: &DeviceFilterMapping) -> Result<(), AppError> {
+        let state = self.known_state()?;
+        let devices = state.devices();
+        let chains: Vec<(&dump::Node, _)> = state.nodes
+            .iter()
+            .zip(&devices)
+            .map(|(node, device)| (node, selector::resolve_chain(mapping, device)))
+            .filter(|(_, chain)| !chain.sections.is_empty())
+            .map(|(node, chain)| (node, chain.eq))
+            .collect();
+        let rendered = filter_chain::render(&chains);
+        let path = self.conf_dir.join(CONF_FILE);
+        let previous = fs::read_to_string(&path).ok();
+        if previous.as_deref() == Some(rendered.as_str()) {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.conf_dir)?;
+        fs::write(&path, rendered)?;
+        info!("wrote {} filter chains to {}", chains.len(), path.display());
+
+        // without a restart the change would not be heard, so the old config stays in place
+        if let Err(e) = (self.reload)() {
+            match previous {
+                Some(contents) => fs::write(&path, contents)?,
+                None => fs::remove_file(&path)?,
+            }
+            return Err(AppError {
+                err_type: e.err_type,
+                message: format!("PipeWire did not pick up the change, restart filter-chain.service by hand: {}", e.message)
+            });
+        }
+        Ok(())
+    }
+}
+
+fn run_pw_dump() -> Result<String, AppError> {
+    let output = Command::new("pw-dump").output().map_err(|e| AppError {
+        err_type: ErrorType::GenericIoError,
+        message: format!("Could not run pw-dump, is PipeWire installed? ({})", e)
+    })?;
+    if !output.status.success() {
+        return Err(AppError { err_type: ErrorType::GenericIoError, message: format!("pw-dump failed: {}", String::from_utf8_lossy(&output.stderr).trim()) });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn restart_filter_chain() -> Result<(), AppError> {
+    let output = Command::new("systemctl").args(["--user", "restart", "filter-chain.service"]).output().map_err(|e| AppError {
+        err_type: ErrorType::GenericIoError,
+        message: format!("Could not run systemctl ({})", e)
+    })?;
+    if !output.status.success() {
+        return Err(AppError { err_type: ErrorType::GenericIoError, message: format!("systemctl failed: {}", String::from_utf8_lossy(&output.stderr).trim()) });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pipewire_backend() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use crate::backend::Direction;
+    use crate::filters::{EqState, FilterBank};
+
+    let dir = crate::storage::TestDir::new("pw-test");
+    let dumps = Arc::new(AtomicUsize::new(0));
+    let counter = dumps.clone();
+    let reloads = Arc::new(AtomicUsize::new(0));
+    let reload_counter = reloads.clone();
+    let backend = PipeWireBackend::with_commands(dir.join("eqplus"), dir.join("conf.d"), Box::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Ok(dump::TEST_DUMP.to_string())
+    }), Box::new(move || {
+        reload_counter.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }));
+    assert!(backend.config_dir().unwrap().ends_with("eqplus"));
+    assert!(dir.join("eqplus").join("config.txt").exists());
+    assert_eq!(backend.enumerate().unwrap().len(), 3);
+
+    let mut mapping = FilterBank::default();
+    mapping.get_mut("all").unwrap().enabled = false;
+    mapping.insert("USB DAC".to_string(), FilterBank::default().remove("all").unwrap());
+    backend.apply(&mapping).unwrap();
+    let conf = fs::read_to_string(dir.join("conf.d").join(CONF_FILE)).unwrap();
+    // without capture sections APO's default stages apply, so the source matches as well
+    assert_eq!(conf.matches("libpipewire-module-filter-chain").count(), 2);
+    assert!(conf.contains("target.object = \"alsa_output.usb-dac.analog-stereo\""));
+    assert_eq!(reloads.load(Ordering::SeqCst), 1);
+    // an unchanged config leaves the service running
+    backend.apply(&mapping).unwrap();
+    assert_eq!(reloads.load(Ordering::SeqCst), 1);
+
+    mapping.insert("USB DAC Mic".to_string(), FilterBank { direction: Direction::Capture, ..FilterBank::new(EqState::default()) });
+    mapping.get_mut("USB DAC").unwrap().enabled = false;
+    backend.apply(&mapping).unwrap();
+    let conf = fs::read_to_string(dir.join("conf.d").join(CONF_FILE)).unwrap();
+    assert_eq!(conf.matches("libpipewire-module-filter-chain").count(), 1);
+    assert!(conf.contains("node.name = \"eqplus.capture.alsa_input.usb-dac.mono\""));
+    assert_eq!(reloads.load(Ordering::SeqCst), 2);
+    // edits reuse the watcher's nodes
+    assert_eq!(dumps.load(Ordering::SeqCst), 1);
+
+    // a failed restart puts the old config back and says so
+    let failing = PipeWireBackend::with_commands(dir.join("eqplus"), dir.join("conf.d"), Box::new(|| Ok(dump::TEST_DUMP.to_string())), Box::new(|| {
+        Err(AppError { err_type: ErrorType::GenericIoError, message: "no systemd".to_string() })
+    }));
+    mapping.get_mut("USB DAC").unwrap().enabled = true;
+    let err = failing.apply(&mapping).unwrap_err();
+    assert!(err.message.contains("restart filter-chain.service"));
+    assert_eq!(fs::read_to_string(dir.join("conf.d").join(CONF_FILE)).unwrap(), conf);
+}
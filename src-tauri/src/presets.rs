@@ -0,0 +1,188 @@
+//! Named presets stored as versioned JSON files under `<app data>/presets`.
+
+use std::{ffi::OsStr, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{AppError, ErrorType};
+use crate::filters::EqState;
+use crate::storage;
+
+pub const PRESETS_DIR: &str = "presets";
+const PRESET_VERSION: u32 = 1;
+const PRESET_EXTENSION: &str = "json";
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PresetMetadata {
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub headphone_model: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Preset {
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub metadata: PresetMetadata,
+    pub eq: EqState,
+    pub created: u64,
+    pub modified: u64,
+}
+
+impl Preset {
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let meta = &self.metadata;
+        self.name.to_lowercase().contains(&query)
+            || meta.author.as_deref().is_some_and(|a| a.to_lowercase().contains(&query))
+            || meta.headphone_model.as_deref().is_some_and(|m| m.to_lowercase().contains(&query))
+            || meta.notes.as_deref().is_some_and(|n| n.to_lowercase().contains(&query))
+            || meta.tags.iter().any(|t| t.to_lowercase().contains(&query))
+    }
+}
+
+pub struct PresetLibrary {
+    dir: PathBuf,
+}
+
+impl PresetLibrary {
+    pub fn new(dir: PathBuf) -> PresetLibrary {
+        PresetLibrary { dir }
+    }
+
+    /// Saves a preset, replacing any existing one with the same (case-insensitive) name. Without
+    /// metadata, the replaced preset's metadata is kept.
+    pub fn save(&self, name: &str, eq: EqState, metadata: Option<PresetMetadata>, now: u64) -> Result<Preset, AppError> {
+        let name = validate_name(name)?;
+        let (created, metadata) = match self.load(&name) {
+            Ok(existing) => (existing.created, metadata.unwrap_or(existing.metadata)),
+            Err(_) => (now, metadata.unwrap_or_default()),
+        };
+        let preset = Preset { version: PRESET_VERSION, name, metadata, eq, created, modified: now };
+        storage::write_json(&self.path_for(&preset.name), &preset)?;
+        Ok(preset)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Preset, AppError> {
+        let path = self.path_for(name);
+        if !path.exists() {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: format!("Could not find preset {}", name) });
+        }
+        let preset: Preset = storage::load_json(&path)?;
+        if preset.version > PRESET_VERSION {
+            return Err(AppError { err_type: ErrorType::InvalidConfig, message: format!("Preset {} was saved by a newer version of eq+", name) });
+        }
+        Ok(preset)
+    }
+
+    /// Lists presets sorted by name, keeping only those matching the query if one is given.
+    pub fn list(&self, query: Option<&str>) -> Result<Vec<Preset>, AppError> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut presets = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new(PRESET_EXTENSION)) {
+                continue;
+            }
+            match storage::load_json::<Preset>(&path) {
+                Ok(preset) => presets.push(preset),
+                Err(e) => log::warn!("Skipping unreadable preset {}: {}", path.display(), e),
+            }
+        }
+        if let Some(q) = query.map(str::trim).filter(|q| !q.is_empty()) {
+            presets.retain(|p| p.matches(q));
+        }
+        presets.sort_by_key(|p| p.name.to_lowercase());
+        Ok(presets)
+    }
+
+    pub fn rename(&self, from: &str, to: &str, now: u64) -> Result<Preset, AppError> {
+        let to = validate_name(to)?;
+        let mut preset = self.load(from)?;
+        let same_file = self.path_for(from) == self.path_for(&to);
+        if !same_file && self.path_for(&to).exists() {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: format!("A preset named {} already exists", to) });
+        }
+        preset.name = to;
+        preset.modified = now;
+        storage::write_json(&self.path_for(&preset.name), &preset)?;
+        if !same_file {
+            fs::remove_file(self.path_for(from))?;
+        }
+        Ok(preset)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), AppError> {
+        let path = self.path_for(name);
+        if !path.exists() {
+            return Err(AppError { err_type: ErrorType::BadArguments, message: format!("Could not find preset {}", name) });
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Names are case-insensitive (so are Windows file names); anything that is not plainly
+    /// filesystem-safe is percent-encoded.
+    fn path_for(&self, name: &str) -> PathBuf {
+        let file_name: String = name
+            .trim()
+            .to_lowercase()
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b' ' => (b as char).to_string(),
+                _ => format!("%{:02x}", b),
+            })
+            .collect();
+        self.dir.join(format!("{}.{}", file_name, PRESET_EXTENSION))
+    }
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError { err_type: ErrorType::BadArguments, message: "Preset name cannot be empty".to_string() });
+    }
+    Ok(name.to_string())
+}
+
+#[test]
+fn test_preset_library() {
+    let dir = crate::storage::TestDir::new("presets");
+    let library = PresetLibrary::new(dir.to_path_buf());
+
+    let metadata = PresetMetadata {
+        author: Some("oratory1990".to_string()),
+        headphone_model: Some("HD 650".to_string()),
+        tags: vec!["harman".to_string()],
+        notes: None,
+    };
+    library.save("HD650 / Harman", EqState::default(), Some(metadata.clone()), 10).unwrap();
+    library.save("Bass boost", EqState::default(), None, 20).unwrap();
+
+    let resaved = library.save("hd650 / harman", EqState::default(), None, 30).unwrap();
+    assert_eq!(resaved.created, 10);
+    assert_eq!(resaved.metadata, metadata);
+    assert_eq!(library.list(None).unwrap().len(), 2);
+
+    assert!(library.save("Loud", EqState::default(), Some(PresetMetadata { tags: vec!["HD 650".to_string()], ..Default::default() }), 40).is_ok());
+    let found = library.list(Some("hd 650")).unwrap();
+    assert_eq!(found.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>(), vec!["hd650 / harman", "Loud"]);
+
+    let cleared = library.save("Loud", EqState::default(), Some(PresetMetadata::default()), 45).unwrap();
+    assert!(cleared.metadata.tags.is_empty());
+
+    library.rename("Bass boost", "Bass boost v2", 50).unwrap();
+    assert!(library.load("Bass boost").is_err());
+    assert_eq!(library.load("bass boost v2").unwrap().modified, 50);
+
+    library.delete("Loud").unwrap();
+    assert_eq!(library.list(None).unwrap().len(), 2);
+}
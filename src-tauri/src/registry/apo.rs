@@ -0,0 +1,148 @@
+//! Where EqualizerAPO leaves its traces in the registry: its own key with the config directory,
+//! and the CLSID of its effect in the FxProperties of every endpoint it was installed on.
+
+use serde::Serialize;
+
+use crate::backend::Direction;
+use crate::errors::AppError;
+
+use super::RegistryReader;
+
+pub const EQUALIZER_APO_KEY: &str = "HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO\\";
+pub const EQUALIZER_APO_CONFIG_VALUE: &str = "ConfigPath";
+pub const RENDER_KEY: &str = "HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render";
+pub const CAPTURE_KEY: &str = "HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Capture";
+/// EqualizerAPO's pre-mix and post-mix effects.
+pub const EQUALIZERAPO_CLSIDS: [&str; 2] = ["{eacd2258-fcac-4ff4-b36d-419e924a6d79}", "{ec1cc9ce-faed-4822-828a-82a81a6f018f}"];
+
+/// The effect slots of an endpoint, each one a value in its FxProperties key.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FxSlot {
+    PreMix,
+    PostMix,
+    Sfx,
+    Mfx,
+    Efx,
+}
+
+impl FxSlot {
+    pub const ALL: [FxSlot; 5] = [FxSlot::PreMix, FxSlot::PostMix, FxSlot::Sfx, FxSlot::Mfx, FxSlot::Efx];
+
+    /// PKEY_FX_PreMixEffectClsid, PostMix, StreamEffect, ModeEffect and EndpointEffect.
+    pub fn property_key(&self) -> &'static str {
+        match self {
+            FxSlot::PreMix => "{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},1",
+            FxSlot::PostMix => "{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},2",
+            FxSlot::Sfx => "{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},5",
+            FxSlot::Mfx => "{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},6",
+            FxSlot::Efx => "{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},7",
+        }
+    }
+
+    /// Whether APO runs its `Stage: post-mix` filters from this slot.
+    pub fn is_post_mix(&self) -> bool {
+        matches!(self, FxSlot::PostMix | FxSlot::Efx)
+    }
+}
+
+pub fn config_dir(registry: &dyn RegistryReader) -> Result<String, AppError> {
+    registry.read_value(EQUALIZER_APO_KEY, EQUALIZER_APO_CONFIG_VALUE)
+}
+
+/// The key holding the endpoints of one direction.
+pub fn endpoints_key(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Render => RENDER_KEY,
+        Direction::Capture => CAPTURE_KEY,
+    }
+}
+
+/// The MMDevices key of an endpoint, from its `PKEY_AudioEndpoint_GUID`.
+pub fn device_key(device_guid: &str, direction: Direction) -> String {
+    format!("{}\\{}", endpoints_key(direction), device_guid.to_lowercase())
+}
+
+/// The effect slots of the endpoint EqualizerAPO is registered in.
+pub fn apo_slots(registry: &dyn RegistryReader, device_guid: &str, direction: Direction) -> Result<Vec<FxSlot>, AppError> {
+    let fx_key = format!("{}\\FxProperties", device_key(device_guid, direction));
+    let mut slots = vec![];
+    for slot in FxSlot::ALL {
+        // endpoints without any effects have no FxProperties key at all
+        if registry.value_exists(&fx_key, slot.property_key()).unwrap_or(false) {
+            let clsid = registry.read_value(&fx_key, slot.property_key())?;
+            if is_equalizer_apo(&clsid) {
+                slots.push(slot);
+            }
+        }
+    }
+    Ok(slots)
+}
+
+/// Whether EqualizerAPO is registered in any of the endpoint's effect slots.
+pub fn apo_installed(registry: &dyn RegistryReader, device_guid: &str, direction: Direction) -> Result<bool, AppError> {
+    Ok(!apo_slots(registry, device_guid, direction)?.is_empty())
+}
+
+pub fn is_equalizer_apo(clsid: &str) -> bool {
+    EQUALIZERAPO_CLSIDS.iter().any(|apo| apo.eq_ignore_ascii_case(clsid.trim()))
+}
+
+/// Trimmed down `reg export` of a machine with EqualizerAPO on the speakers (after the mix, behind a
+/// vendor effect) and the microphone: the headphones run a vendor effect, the HDMI output has no
+/// effects at all.
+#[cfg(test)]
+pub const TEST_REG_EXPORT: &str = r#"Windows Registry Editor Version 5.00
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\EqualizerAPO]
+"ConfigPath"="C:\\Program Files\\EqualizerAPO\\config"
+"Version"=dword:00000001
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0001}]
+"DeviceState"=dword:00000001
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0001}\FxProperties]
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},1"="{62dc1a93-ae24-464c-a43e-452f824c4250}"
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},2"="{EC1CC9CE-FAED-4822-828A-82A81A6F018F}"
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},3"="{637c490d-eee3-4c0a-973f-371958802da2}"
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},7"="{ec1cc9ce-faed-4822-828a-82a81a6f018f}"
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0002}]
+"DeviceState"=dword:00000001
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0002}\FxProperties]
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},5"="{c9453e73-8c5c-4463-9984-af8bab2f5447}"
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},8"=hex:01,00,00,00
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0003}]
+"DeviceState"=dword:00000001
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Capture\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0101}]
+"DeviceState"=dword:00000001
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Capture\{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0101}\FxProperties]
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},1"="{eacd2258-fcac-4ff4-b36d-419e924a6d79}"
+"#;
+
+#[test]
+fn test_apo_detection() {
+    let registry = super::RegFile::parse(TEST_REG_EXPORT).unwrap();
+    assert_eq!(config_dir(&registry).unwrap(), "C:\\Program Files\\EqualizerAPO\\config");
+    assert!(apo_installed(&registry, "{1FD2A0A3-5E5C-4A9B-9D7E-3F0C2B1A0001}", Direction::Render).unwrap());
+    assert!(!apo_installed(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0002}", Direction::Render).unwrap());
+    assert!(!apo_installed(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0003}", Direction::Render).unwrap());
+    assert!(!apo_installed(&registry, "{00000000-0000-0000-0000-000000000000}", Direction::Render).unwrap());
+    assert!(apo_installed(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0101}", Direction::Capture).unwrap());
+    assert!(!apo_installed(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0101}", Direction::Render).unwrap());
+
+    // the speakers run a vendor effect before the mix and APO after it
+    let slots = apo_slots(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0001}", Direction::Render).unwrap();
+    assert_eq!(slots, vec![FxSlot::PostMix, FxSlot::Efx]);
+    assert!(slots.iter().all(FxSlot::is_post_mix));
+    let slots = apo_slots(&registry, "{1fd2a0a3-5e5c-4a9b-9d7e-3f0c2b1a0101}", Direction::Capture).unwrap();
+    assert_eq!(slots, vec![FxSlot::PreMix]);
+    assert!(!slots.iter().any(FxSlot::is_post_mix));
+
+    let without_apo = super::RegFile::parse("Windows Registry Editor Version 5.00\r\n").unwrap();
+    assert!(config_dir(&without_apo).is_err());
+}
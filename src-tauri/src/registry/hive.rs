@@ -0,0 +1,341 @@
+//! Parser for the two text formats registries get dumped to: the hives Wine keeps in its prefix
+//! (`system.reg`, `user.reg`) and regedit's `.reg` exports. Values are written the same way in
+//! both; key names are escaped and relative to the hive's root in Wine's files, while regedit
+//! writes them verbatim and root-qualified.
+//!
+//! ```text
+//! WINE REGISTRY Version 2
+//! ;; All keys relative to \\Machine
+//!
+//! [Software\\EqualizerAPO] 1690000000
+//! #time=1d9c2f0e8a1b2c3
+//! "ConfigPath"="C:\\Program Files\\EqualizerAPO\\config"
+//! "Version"=dword:00000001
+//! ```
+//!
+//! ```text
+//! Windows Registry Editor Version 5.00
+//!
+//! [HKEY_LOCAL_MACHINE\SOFTWARE\EqualizerAPO]
+//! "ConfigPath"="C:\\Program Files\\EqualizerAPO\\config"
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::{AppError, ErrorType};
+
+/// How key names are written between the brackets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyNames {
+    /// Wine: `[Software\\EqualizerAPO]`
+    Escaped,
+    /// regedit: `[HKEY_LOCAL_MACHINE\SOFTWARE\EqualizerAPO]`
+    Verbatim,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegValue {
+    String(String),
+    ExpandString(String),
+    MultiString(Vec<String>),
+    Dword(u32),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Key {
+    values: HashMap<String, RegValue>,
+}
+
+/// One parsed hive. Key and value names are case-insensitive, like in Windows.
+#[derive(Debug, Clone, Default)]
+pub struct Hive {
+    keys: HashMap<String, Key>,
+    names: HashMap<String, String>,
+}
+
+impl Hive {
+    pub fn load(path: &Path, key_names: KeyNames) -> Result<Hive, AppError> {
+        let bytes = fs::read(path).map_err(|e| AppError {
+            err_type: ErrorType::RegistryError,
+            message: format!("Could not read registry file {}: {}", path.display(), e)
+        })?;
+        // regedit writes UTF-16, Wine and hand-edited exports UTF-8
+        let contents = match bytes.strip_prefix(&[0xff, 0xfe]) {
+            Some(utf16) => utf16_string(utf16),
+            None => String::from_utf8_lossy(&bytes).to_string(),
+        };
+        Hive::parse(&contents, key_names)
+    }
+
+    pub fn parse(contents: &str, key_names: KeyNames) -> Result<Hive, AppError> {
+        let mut hive = Hive::default();
+        let mut current: Option<String> = None;
+        for (number, line) in logical_lines(contents) {
+            // a byte order mark that survived decoding
+            let line = line.trim().trim_start_matches('\u{feff}');
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') || is_header(line) {
+                continue;
+            }
+            if line.starts_with('[') {
+                let end = line.rfind(']').ok_or_else(|| reg_err(number, "Unterminated key name"))?;
+                let raw = &line[1..end];
+                if raw.starts_with('-') {
+                    // a deleted key in a regedit export
+                    current = None;
+                    continue;
+                }
+                let name = match key_names {
+                    KeyNames::Escaped => unescape(raw).ok_or_else(|| reg_err(number, "Invalid escape in key name"))?,
+                    KeyNames::Verbatim => raw.to_string(),
+                };
+                current = Some(hive.insert_key(&name));
+                continue;
+            }
+            let (name, data) = parse_value_line(line).ok_or_else(|| reg_err(number, "Malformed value"))?;
+            let key = current.as_ref().ok_or_else(|| reg_err(number, "Value outside of a key"))?;
+            if let Some(data) = data {
+                hive.keys.get_mut(key).unwrap().values.insert(name.to_lowercase(), data);
+            }
+        }
+        Ok(hive)
+    }
+
+    pub fn value(&self, key: &str, name: &str) -> Option<&RegValue> {
+        self.keys.get(&normalize(key))?.values.get(&name.to_lowercase())
+    }
+
+    pub fn key_exists(&self, key: &str) -> bool {
+        self.keys.contains_key(&normalize(key))
+    }
+
+    /// Names of the direct children of `key`, as spelled in the hive.
+    pub fn subkeys(&self, key: &str) -> Vec<String> {
+        let prefix = format!("{}\\", normalize(key));
+        let mut children: Vec<String> = self.names
+            .iter()
+            .filter_map(|(lower, name)| {
+                let rest = lower.strip_prefix(&prefix)?;
+                if rest.contains('\\') { None } else { Some(name[prefix.len()..].to_string()) }
+            })
+            .collect();
+        children.sort();
+        children
+    }
+
+    fn insert_key(&mut self, name: &str) -> String {
+        let lower = normalize(name);
+        self.names.entry(lower.clone()).or_insert_with(|| name.trim_matches('\\').to_string());
+        self.keys.entry(lower.clone()).or_default();
+        lower
+    }
+}
+
+/// Physical lines joined where a value continues onto the next line with a trailing `\`.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    let mut continued = false;
+    for (i, line) in contents.lines().enumerate() {
+        if continued {
+            let last = lines.last_mut().unwrap();
+            last.1.push_str(line.trim_start());
+        } else {
+            lines.push((i + 1, line.to_string()));
+        }
+        let last = &mut lines.last_mut().unwrap().1;
+        // only binary data is wrapped
+        continued = last.ends_with('\\') && last.contains("=hex");
+        if continued {
+            last.pop();
+        }
+    }
+    lines
+}
+
+fn is_header(line: &str) -> bool {
+    line.starts_with("WINE REGISTRY") || line.starts_with("Windows Registry Editor") || line == "REGEDIT4"
+}
+
+/// A `name=data` line; the data is `None` for a deleted value (`"name"=-`).
+fn parse_value_line(line: &str) -> Option<(String, Option<RegValue>)> {
+    let (name, rest) = if let Some(rest) = line.strip_prefix('@') {
+        (String::new(), rest)
+    } else {
+        let (name, len) = quoted(line)?;
+        (name, &line[len..])
+    };
+    let data = rest.trim_start().strip_prefix('=')?.trim();
+    if data == "-" {
+        return Some((name, None));
+    }
+    Some((name, Some(parse_data(data)?)))
+}
+
+fn parse_data(data: &str) -> Option<RegValue> {
+    if data.starts_with('"') {
+        return Some(RegValue::String(quoted(data)?.0));
+    }
+    if let Some(rest) = data.strip_prefix("str(2):") {
+        return Some(RegValue::ExpandString(quoted(rest)?.0));
+    }
+    if let Some(rest) = data.strip_prefix("str(7):") {
+        let joined = quoted(rest)?.0;
+        return Some(RegValue::MultiString(joined.split('\0').filter(|s| !s.is_empty()).map(str::to_string).collect()));
+    }
+    if let Some(rest) = data.strip_prefix("dword:") {
+        return u32::from_str_radix(rest.trim(), 16).ok().map(RegValue::Dword);
+    }
+    let (kind, bytes) = data.split_once(':')?;
+    if kind != "hex" && !(kind.starts_with("hex(") && kind.ends_with(')')) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = bytes
+        .split(',')
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect();
+    let bytes = bytes?;
+    Some(match kind {
+        "hex(1)" => RegValue::String(utf16_string(&bytes)),
+        "hex(2)" => RegValue::ExpandString(utf16_string(&bytes)),
+        "hex(4)" if bytes.len() == 4 => RegValue::Dword(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        "hex(7)" => RegValue::MultiString(utf16_string(&bytes).split('\0').filter(|s| !s.is_empty()).map(str::to_string).collect()),
+        _ => RegValue::Binary(bytes),
+    })
+}
+
+/// Parses a quoted string at the start of `s`, returning it and the number of bytes consumed.
+fn quoted(s: &str) -> Option<(String, usize)> {
+    let body = s.strip_prefix('"')?;
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match (escaped, c) {
+            (true, _) => escaped = false,
+            (false, '\\') => escaped = true,
+            (false, '"') => return Some((unescape(&body[..i])?, i + 2)),
+            _ => {},
+        }
+    }
+    None
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            'x' => {
+                let mut hex = String::new();
+                while hex.len() < 4 && chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    hex.push(chars.next().unwrap());
+                }
+                out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            },
+            other => out.push(other),
+        }
+    }
+    Some(out)
+}
+
+fn utf16_string(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').to_string()
+}
+
+fn normalize(key: &str) -> String {
+    key.trim_matches('\\').to_lowercase()
+}
+
+fn reg_err(number: usize, message: &str) -> AppError {
+    AppError { err_type: ErrorType::RegistryError, message: format!("Line {}: {}", number, message) }
+}
+
+#[cfg(test)]
+pub const TEST_SYSTEM_REG: &str = r#"WINE REGISTRY Version 2
+;; All keys relative to \\Machine
+
+#arch=win64
+
+[Software\\EqualizerAPO] 1690000000
+#time=1d9c2f0e8a1b2c3
+"ConfigPath"="C:\\Program Files\\EqualizerAPO\\config"
+"InstallPath"=str(2):"C:\\Program Files\\EqualizerAPO"
+"EnableTrace"=dword:00000000
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{0a1b2c3d-0000-4000-8000-000000000001}] 1690000000
+"DeviceState"=dword:00000001
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{0a1b2c3d-0000-4000-8000-000000000001}\\FxProperties] 1690000000
+"{d04e05a6-594b-4fb6-a80d-01af5eed7d1d},1"="{EACD2258-FCAC-4FF4-B36D-419E924A6D79}"
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{0a1b2c3d-0000-4000-8000-000000000001}\\Properties] 1690000000
+"{a45c254e-df1c-4efd-8020-67d146a850e0},2"="Speakers"
+"{a45c254e-df1c-4efd-8020-67d146a850e0},14"="Speakers (Wine PulseAudio)"
+"{b3f8fa53-0004-438e-9003-51a46e139bfc},6"=hex:01,02,\
+  03,04
+"{f19f064d-082c-4e27-bc73-6882a1bb8e4c},0"=hex:fe,ff,02,00,80,bb,00,00,00,dc,05,00,08,00,20,00,16,00,18,00,03,00,00,00,\
+  01,00,00,00,00,00,10,00,80,00,00,aa,00,38,9b,71
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{0a1b2c3d-0000-4000-8000-000000000002}] 1690000000
+"DeviceState"=dword:00000004
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{0a1b2c3d-0000-4000-8000-000000000002}\\Properties] 1690000000
+"{a45c254e-df1c-4efd-8020-67d146a850e0},14"="HDMI \"TV\""
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Capture\\{0a1b2c3d-0000-4000-8000-000000000101}] 1690000000
+"DeviceState"=dword:00000001
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Capture\\{0a1b2c3d-0000-4000-8000-000000000101}\\Properties] 1690000000
+"{a45c254e-df1c-4efd-8020-67d146a850e0},14"="Microphone (Wine PulseAudio)"
+"{f19f064d-082c-4e27-bc73-6882a1bb8e4c},0"=hex:41,00,00,00,12,00,00,00,01,00,01,00,44,ac,00,00,88,58,01,00,02,00,10,\
+  00,00,00
+"#;
+
+#[test]
+fn test_parse_wine_hive() {
+    let hive = Hive::parse(TEST_SYSTEM_REG, KeyNames::Escaped).unwrap();
+    assert_eq!(
+        hive.value("SOFTWARE\\EqualizerAPO", "configpath"),
+        Some(&RegValue::String("C:\\Program Files\\EqualizerAPO\\config".to_string()))
+    );
+    assert_eq!(hive.value("Software\\EqualizerAPO\\", "EnableTrace"), Some(&RegValue::Dword(0)));
+    assert!(matches!(hive.value("Software\\EqualizerAPO", "InstallPath"), Some(RegValue::ExpandString(_))));
+
+    let render = "Software\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render";
+    assert_eq!(hive.subkeys(render), vec!["{0a1b2c3d-0000-4000-8000-000000000001}", "{0a1b2c3d-0000-4000-8000-000000000002}"]);
+    let props = format!("{}\\{{0a1b2c3d-0000-4000-8000-000000000001}}\\Properties", render);
+    assert_eq!(hive.value(&props, "{b3f8fa53-0004-438e-9003-51a46e139bfc},6"), Some(&RegValue::Binary(vec![1, 2, 3, 4])));
+    let props = format!("{}\\{{0a1b2c3d-0000-4000-8000-000000000002}}\\Properties", render);
+    assert_eq!(hive.value(&props, "{a45c254e-df1c-4efd-8020-67d146a850e0},14"), Some(&RegValue::String("HDMI \"TV\"".to_string())));
+
+    assert!(Hive::parse("\"orphan\"=\"value\"", KeyNames::Escaped).unwrap_err().message.starts_with("Line 1"));
+}
+
+#[test]
+fn test_parse_reg_export() {
+    let export = "\u{feff}Windows Registry Editor Version 5.00\r\n\r\n\
+        [HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO]\r\n\
+        \"ConfigPath\"=\"C:\\\\Program Files\\\\EqualizerAPO\\\\config\"\r\n\
+        \"Obsolete\"=-\r\n\
+        \"InstallPath\"=hex(2):43,00,3a,00,5c,00,00,00\r\n\
+        [-HKEY_LOCAL_MACHINE\\SOFTWARE\\Removed]\r\n";
+    let hive = Hive::parse(export, KeyNames::Verbatim).unwrap();
+    assert_eq!(
+        hive.value("HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO", "ConfigPath"),
+        Some(&RegValue::String("C:\\Program Files\\EqualizerAPO\\config".to_string()))
+    );
+    assert_eq!(hive.value("HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO", "InstallPath"), Some(&RegValue::ExpandString("C:\\".to_string())));
+    assert_eq!(hive.value("HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO", "Obsolete"), None);
+    assert!(!hive.key_exists("HKEY_LOCAL_MACHINE\\SOFTWARE\\Removed"));
+}
@@ -0,0 +1,115 @@
+//! Read access to the Windows registry, wherever it comes from: the live registry on Windows, a
+//! Wine prefix, or a `.reg` export captured on someone's machine.
+//!
+//! Keys are always written root-qualified (`HKEY_LOCAL_MACHINE\SOFTWARE\EqualizerAPO`), so code
+//! that only reads values, like the APO detection in `apo`, runs unchanged against all of them.
+
+pub mod apo;
+pub mod hive;
+
+use std::path::Path;
+
+use crate::errors::{AppError, ErrorType};
+
+use hive::{Hive, KeyNames, RegValue};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootKey {
+    ClassesRoot,
+    CurrentConfig,
+    CurrentUser,
+    LocalMachine,
+    Users,
+}
+
+impl RootKey {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RootKey::ClassesRoot => "HKEY_CLASSES_ROOT",
+            RootKey::CurrentConfig => "HKEY_CURRENT_CONFIG",
+            RootKey::CurrentUser => "HKEY_CURRENT_USER",
+            RootKey::LocalMachine => "HKEY_LOCAL_MACHINE",
+            RootKey::Users => "HKEY_USERS",
+        }
+    }
+}
+
+pub trait RegistryReader {
+    /// Reads a string value. Missing values and values of another type are errors.
+    fn read_value(&self, key: &str, value: &str) -> Result<String, AppError>;
+
+    fn value_exists(&self, key: &str, value: &str) -> Result<bool, AppError>;
+
+    /// Splits a root-qualified key into the path below the root and the root itself.
+    fn split_key(&self, key: &str) -> Result<(String, RootKey), AppError> {
+        split_key(key)
+    }
+}
+
+pub fn split_key(key: &str) -> Result<(String, RootKey), AppError> {
+    let (root_part, path_part) = key
+        .split_once('\\')
+        .ok_or(AppError { err_type: ErrorType::RegistryError, message: format!("Registry key was an invalid format: {}", key) })?;
+    let root_key = match root_part.to_uppercase().as_str() {
+        "HKEY_CLASSES_ROOT" => RootKey::ClassesRoot,
+        "HKEY_CURRENT_CONFIG" => RootKey::CurrentConfig,
+        "HKEY_CURRENT_USER" => RootKey::CurrentUser,
+        "HKEY_LOCAL_MACHINE" => RootKey::LocalMachine,
+        "HKEY_USERS" => RootKey::Users,
+        _ => return Err(AppError { err_type: ErrorType::RegistryError, message: format!("Unexpected root key {}", root_part) }),
+    };
+    Ok((path_part.to_string(), root_key))
+}
+
+/// `read_value` on top of a parsed hive, shared by the Wine registry and `.reg` exports.
+pub fn read_string(value: Option<&RegValue>) -> Result<String, AppError> {
+    match value {
+        Some(RegValue::String(s)) | Some(RegValue::ExpandString(s)) => Ok(s.clone()),
+        Some(_) => Err(AppError { err_type: ErrorType::RegistryError, message: "Registry value was the wrong type".to_string() }),
+        None => Err(AppError { err_type: ErrorType::RegistryError, message: "Error reading registry value".to_string() }),
+    }
+}
+
+/// A registry export as written by `regedit /e` or `reg export`. It can hold keys from any root.
+pub struct RegFile {
+    hive: Hive,
+}
+
+impl RegFile {
+    pub fn load(path: &Path) -> Result<RegFile, AppError> {
+        Ok(RegFile { hive: Hive::load(path, KeyNames::Verbatim)? })
+    }
+
+    pub fn parse(contents: &str) -> Result<RegFile, AppError> {
+        Ok(RegFile { hive: Hive::parse(contents, KeyNames::Verbatim)? })
+    }
+
+    fn qualified(&self, key: &str) -> Result<String, AppError> {
+        let (path, root) = self.split_key(key)?;
+        Ok(format!("{}\\{}", root.name(), path))
+    }
+}
+
+impl RegistryReader for RegFile {
+    fn read_value(&self, key: &str, value: &str) -> Result<String, AppError> {
+        read_string(self.hive.value(&self.qualified(key)?, value))
+    }
+
+    fn value_exists(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        Ok(self.hive.value(&self.qualified(key)?, value).is_some())
+    }
+}
+
+#[test]
+fn test_reg_file() {
+    let reg = RegFile::parse(apo::TEST_REG_EXPORT).unwrap();
+    assert_eq!(
+        reg.read_value("hkey_local_machine\\Software\\EqualizerAPO\\", "ConfigPath").unwrap(),
+        "C:\\Program Files\\EqualizerAPO\\config"
+    );
+    assert!(!reg.value_exists("HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO", "Missing").unwrap());
+    assert!(!reg.value_exists("HKEY_CURRENT_USER\\SOFTWARE\\EqualizerAPO", "ConfigPath").unwrap());
+    assert!(reg.read_value("HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO", "Version").is_err());
+    assert!(reg.read_value("HKLM\\SOFTWARE\\EqualizerAPO", "ConfigPath").is_err());
+    assert_eq!(split_key("HKEY_CURRENT_USER\\Software").unwrap(), ("Software".to_string(), RootKey::CurrentUser));
+}
@@ -0,0 +1,132 @@
+//! Magnitude response of an `EqState`, computed from the same RBJ cookbook biquads APO uses.
+
+use std::f64::consts::PI;
+
+use crate::filters::{self, EqState, FilterParams, FilterType};
+
+pub const DEFAULT_SAMPLE_RATE: f64 = 48000.0;
+
+/// Normalized biquad coefficients (`a0` divided out).
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn from_filter(filter: &FilterParams, sample_rate: f64) -> Biquad {
+        let w0 = 2.0 * PI * filter.frequency.min(sample_rate / 2.0 - 1.0) / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * filter.q);
+        let a = 10f64.powf(filter.gain / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match filter.filter_type {
+            FilterType::AllPass => (1.0 - alpha, -2.0 * cos, 1.0 + alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Peaking => (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+            FilterType::LowShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                    (a + 1.0) + (a - 1.0) * cos + sq,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sq,
+                )
+            },
+            FilterType::HighShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                    (a + 1.0) - (a - 1.0) * cos + sq,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sq,
+                )
+            },
+        };
+        Biquad { b0: b0 / a0, b1: b1 / a0, b2: b2 / a0, a1: a1 / a0, a2: a2 / a0 }
+    }
+
+    fn magnitude_db(&self, frequency: f64, sample_rate: f64) -> f64 {
+        let w = 2.0 * PI * frequency / sample_rate;
+        let (s1, c1) = w.sin_cos();
+        let (s2, c2) = (2.0 * w).sin_cos();
+        let num_re = self.b0 + self.b1 * c1 + self.b2 * c2;
+        let num_im = -(self.b1 * s1 + self.b2 * s2);
+        let den_re = 1.0 + self.a1 * c1 + self.a2 * c2;
+        let den_im = -(self.a1 * s1 + self.a2 * s2);
+        let power = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im);
+        10.0 * power.max(1e-30).log10()
+    }
+}
+
+/// `count` frequencies spaced evenly on a log scale between `start` and `end`.
+pub fn log_frequencies(start: f64, end: f64, count: usize) -> Vec<f64> {
+    if count < 2 {
+        return vec![start];
+    }
+    let step = (end / start).ln() / (count - 1) as f64;
+    (0..count).map(|i| start * (step * i as f64).exp()).collect()
+}
+
+/// Total response of the EQ in dB (preamp included) at each of the given frequencies.
+pub fn eq_response(eq: &EqState, frequencies: &[f64], sample_rate: f64) -> Vec<f64> {
+    let biquads: Vec<Biquad> = eq.filters.iter().map(|f| Biquad::from_filter(f, sample_rate)).collect();
+    frequencies
+        .iter()
+        .map(|freq| {
+            eq.preamp
+                + filters::graphic_eq_gain(&eq.graphic_eq, *freq)
+                + biquads.iter().map(|b| b.magnitude_db(*freq, sample_rate)).sum::<f64>()
+        })
+        .collect()
+}
+
+/// A-weighting in dB, used to approximate how loud a response is perceived to be.
+fn a_weighting(frequency: f64) -> f64 {
+    let f2 = frequency * frequency;
+    let num = 12194f64.powi(2) * f2 * f2;
+    let den = (f2 + 20.6f64.powi(2)) * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt() * (f2 + 12194f64.powi(2));
+    20.0 * (num / den).log10() + 2.0
+}
+
+/// Perceived loudness of an EQ relative to a flat response, in dB.
+///
+/// This is the A-weighted power average of the response over the audible range; it is only
+/// meaningful for comparing EQs against each other, not as an absolute level.
+pub fn perceived_loudness(eq: &EqState, sample_rate: f64) -> f64 {
+    let frequencies = log_frequencies(20.0, 20000.0, 256);
+    let response = eq_response(eq, &frequencies, sample_rate);
+    let (mut weighted, mut total) = (0.0, 0.0);
+    for (freq, db) in frequencies.iter().zip(response) {
+        let weight = 10f64.powf(a_weighting(*freq) / 10.0);
+        weighted += weight * 10f64.powf(db / 10.0);
+        total += weight;
+    }
+    10.0 * (weighted / total).log10()
+}
+
+#[test]
+fn test_eq_response() {
+    let peak = EqState {
+        preamp: -2.0,
+        filters: vec![FilterParams { id: "1".to_string(), frequency: 1000.0, gain: 6.0, q: 1.0, filter_type: FilterType::Peaking }],
+        graphic_eq: vec![],
+    };
+    let response = eq_response(&peak, &[20.0, 1000.0, 20000.0], DEFAULT_SAMPLE_RATE);
+    assert!((response[0] + 2.0).abs() < 0.1);
+    assert!((response[1] - 4.0).abs() < 0.01);
+    assert!((response[2] + 2.0).abs() < 0.1);
+
+    let flat = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    assert!(perceived_loudness(&flat, DEFAULT_SAMPLE_RATE).abs() < 1e-9);
+    assert!(perceived_loudness(&peak, DEFAULT_SAMPLE_RATE) > 0.0);
+}
@@ -0,0 +1,246 @@
+//! Import and export of Room EQ Wizard filter settings files.
+//!
+//! REW writes one `Filter  N:` row per filter, padded into columns. The row layout depends on the
+//! equalizer the filters were generated for (the `Equaliser:` header): most use `Q`, some give
+//! the bandwidth in octaves (`BW`) or in sixtieths of an octave (`BW/60`), and the generic shelf
+//! filters have a fixed slope instead of a Q.
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::filters::{self, at_line, EqState, FilterParams, FilterType};
+
+/// Q of REW's fixed-slope (12 dB/octave) shelves and its plain LP/HP filters.
+const BUTTERWORTH_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RewEquipment {
+    Generic,
+    EqualizerApo,
+}
+
+impl RewEquipment {
+    fn header_name(&self) -> &'static str {
+        match self {
+            RewEquipment::Generic => "Generic",
+            RewEquipment::EqualizerApo => "Equalizer APO",
+        }
+    }
+}
+
+/// Reads the filters of a REW export. Filters that are off or unused are skipped; a `Preamp:`
+/// line (written for Equalizer APO) is kept.
+pub fn import(contents: &str) -> Result<EqState, AppError> {
+    let mut eq = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    for (i, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.starts_with("Preamp:") {
+            eq.preamp = filters::process_preamp_line(line).map_err(|e| at_line(i + 1, e))?;
+        } else if line.starts_with("Filter") && line.contains(':') {
+            if let Some(mut filter) = process_rew_filter_line(line).map_err(|e| at_line(i + 1, e))? {
+                filter.id = (eq.filters.len() + 1).to_string();
+                eq.filters.push(filter);
+            }
+        }
+        // everything else is REW's header (version, date, notes, equaliser, averages)
+    }
+    if eq.filters.is_empty() {
+        return Err(AppError::invalid_config("The file does not contain any active filters".to_string()));
+    }
+    Ok(eq)
+}
+
+/// Parses one REW filter row. Returns `None` for rows that are switched off or have no filter.
+pub fn process_rew_filter_line(line: &str) -> Result<Option<FilterParams>, AppError> {
+    let (head, rest) = line.split_once(':').ok_or(AppError::invalid_config(format!("Malformed filter line: {}", line)))?;
+    let id = head.trim_start_matches("Filter").trim();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AppError::invalid_config(format!("Malformed filter line: {}", line)));
+    }
+    let tokens: Vec<&str> = rest.split_whitespace().collect();
+    match tokens.first() {
+        Some(&"ON") => {},
+        Some(&"OFF") | Some(&"None") => return Ok(None),
+        _ => return Err(AppError::invalid_config(format!("Malformed filter line (missing values): {}", line))),
+    }
+    let raw_type = tokens.get(1).cloned().unwrap_or("");
+    if raw_type == "None" {
+        return Ok(None);
+    }
+
+    let mut filter = FilterParams { id: id.to_string(), frequency: 0.0, gain: 0.0, q: BUTTERWORTH_Q, filter_type: FilterType::Peaking };
+    let mut has_q = false;
+    let mut index = 2;
+    // the slope of REW's "LS 6dB"/"HS 12dB" shelves sits between the type and Fc
+    let slope = match tokens.get(index) {
+        Some(t) if t.ends_with("dB") && t.len() > 2 => {
+            index += 1;
+            Some(*t)
+        },
+        _ => None,
+    };
+    while index < tokens.len() {
+        let value = tokens.get(index + 1).map(|v| v.parse::<f64>());
+        match (tokens[index], value) {
+            ("Fc", Some(Ok(fc))) => {
+                filter.frequency = match tokens.get(index + 2) {
+                    Some(&"kHz") => fc * 1000.0,
+                    Some(&"Hz") => fc,
+                    _ => return Err(AppError::invalid_config(format!("Malformed filter line (missing or invalid values): {}", line))),
+                };
+                index += 3;
+            },
+            ("Gain", Some(Ok(gain))) => {
+                if tokens.get(index + 2) != Some(&"dB") {
+                    return Err(AppError::invalid_config(format!("Malformed filter line (missing or invalid values): {}", line)));
+                }
+                filter.gain = gain;
+                index += 3;
+            },
+            ("Q", Some(Ok(q))) => {
+                filter.q = q;
+                has_q = true;
+                index += 2;
+            },
+            ("BW", Some(Ok(octaves))) => {
+                filter.q = bandwidth_to_q(octaves);
+                has_q = true;
+                index += if tokens.get(index + 2) == Some(&"Oct") { 3 } else { 2 };
+            },
+            ("BW/60", Some(Ok(sixtieths))) => {
+                filter.q = bandwidth_to_q(sixtieths / 60.0);
+                has_q = true;
+                index += 2;
+            },
+            ("Fc", _) => return Err(AppError::invalid_config(format!("Malformed filter line (invalid frequency): {}", line))),
+            ("Gain", _) => return Err(AppError::invalid_config(format!("Malformed filter line (invalid gain): {}", line))),
+            ("Q", _) | ("BW", _) | ("BW/60", _) => return Err(AppError::invalid_config(format!("Malformed filter line (invalid Q): {}", line))),
+            // REW's modal filters carry a decay time we have no use for
+            _ => index += 1,
+        }
+    }
+
+    filter.filter_type = match (raw_type, slope) {
+        ("PK", None) | ("Modal", None) => FilterType::Peaking,
+        ("LP", None) | ("LPQ", None) => FilterType::LowPass,
+        ("HP", None) | ("HPQ", None) => FilterType::HighPass,
+        ("BP", None) => FilterType::BandPass,
+        ("NO", None) => FilterType::Notch,
+        ("AP", None) => FilterType::AllPass,
+        ("LS", None) | ("LSC", None) | ("LSQ", None) | ("LS", Some("12dB")) => FilterType::LowShelf,
+        ("HS", None) | ("HSC", None) | ("HSQ", None) | ("HS", Some("12dB")) => FilterType::HighShelf,
+        (t, Some(s)) => return Err(AppError::invalid_config(format!("Unsupported filter type: {} {}", t, s))),
+        (t, None) => return Err(AppError::invalid_config(format!("Unsupported filter type: {}", t))),
+    };
+    if filter.frequency <= 0.0 {
+        return Err(AppError::invalid_config(format!("Malformed filter line (missing values): {}", line)));
+    }
+    if !has_q && matches!(filter.filter_type, FilterType::Peaking | FilterType::BandPass | FilterType::Notch | FilterType::AllPass) && raw_type != "Modal" {
+        return Err(AppError::invalid_config(format!("Malformed filter line (missing Q or bandwidth): {}", line)));
+    }
+    if filter.q <= 0.0 {
+        return Err(AppError::invalid_config(format!("Malformed filter line (invalid Q): {}", line)));
+    }
+    Ok(Some(filter))
+}
+
+/// Writes the filters as a REW filter settings file for the given equipment type. The preamp is
+/// only written for Equalizer APO; REW's other equalizers have no notion of one.
+pub fn export(eq: &EqState, equipment: RewEquipment) -> Result<String, AppError> {
+    if !eq.graphic_eq.is_empty() {
+        return Err(AppError::bad_arguments("GraphicEQ curves can not be exported as REW filters".to_string()));
+    }
+    let mut lines = vec![
+        "Filter Settings file".to_string(),
+        String::new(),
+        "Notes:Exported from eq+".to_string(),
+        String::new(),
+        format!("Equaliser: {}", equipment.header_name()),
+    ];
+    if equipment == RewEquipment::EqualizerApo {
+        lines.push(format!("Preamp: {:.1} dB", eq.preamp));
+    }
+    for (i, filter) in eq.filters.iter().enumerate() {
+        lines.push(match equipment {
+            RewEquipment::Generic => generic_row(i + 1, filter),
+            RewEquipment::EqualizerApo => FilterParams { id: (i + 1).to_string(), ..filter.clone() }.to_apo_line(),
+        });
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// A row in REW's padded column layout, e.g. `Filter  1: ON  PK       Fc   63.0 Hz  Gain  -4.5 dB  Q  2.00`.
+fn generic_row(number: usize, filter: &FilterParams) -> String {
+    let (filter_type, has_gain) = match filter.filter_type {
+        FilterType::Peaking => ("PK", true),
+        FilterType::LowPass => ("LPQ", false),
+        FilterType::HighPass => ("HPQ", false),
+        FilterType::BandPass => ("BP", false),
+        FilterType::Notch => ("NO", false),
+        FilterType::AllPass => ("AP", false),
+        FilterType::LowShelf => ("LSC", true),
+        FilterType::HighShelf => ("HSC", true),
+    };
+    let mut row = format!("Filter {:>2}: ON  {:<8} Fc {:>7.1} Hz", number, filter_type, filter.frequency);
+    if has_gain {
+        row.push_str(&format!("  Gain {:>5.1} dB", filter.gain));
+    }
+    row.push_str(&format!("  Q {:>5.2}", filter.q));
+    row
+}
+
+/// Q of a filter whose bandwidth is `octaves` wide.
+fn bandwidth_to_q(octaves: f64) -> f64 {
+    let n = 2f64.powf(octaves);
+    n.sqrt() / (n - 1.0)
+}
+
+
+#[test]
+fn test_rew_import() {
+    let generic = "Filter Settings file\n\
+        \n\
+        Room EQ V5.20\n\
+        Dated: Mar 3, 2024 9:41:12 PM\n\
+        \n\
+        Notes:\n\
+        \n\
+        Equaliser: Generic\n\
+        Averages\n\
+        Filter  1: ON  PK       Fc   63.0 Hz  Gain  -4.5 dB  Q  2.00\n\
+        Filter  2: ON  LS       Fc    105 Hz  Gain   5.5 dB\n\
+        Filter  3: ON  PK       Fc  1.200 kHz  Gain  -2.0 dB  BW/60 60\n\
+        Filter  4: ON  HS 12dB  Fc   8000 Hz  Gain  -3.0 dB\n\
+        Filter  5: OFF PK       Fc    250 Hz  Gain   1.0 dB  Q  1.00\n\
+        Filter  6: None\n";
+    let eq = import(generic).unwrap();
+    assert_eq!(eq.filters.len(), 4);
+    assert_eq!(eq.filters[0].q, 2.0);
+    assert!(matches!(eq.filters[1].filter_type, FilterType::LowShelf));
+    assert_eq!(eq.filters[1].q, BUTTERWORTH_Q);
+    assert_eq!(eq.filters[2].frequency, 1200.0);
+    assert!((eq.filters[2].q - std::f64::consts::SQRT_2).abs() < 1e-9);
+    assert!(matches!(eq.filters[3].filter_type, FilterType::HighShelf));
+    assert_eq!(eq.filters[3].id, "4");
+
+    let exported = export(&eq, RewEquipment::Generic).unwrap();
+    assert!(exported.contains("Filter  1: ON  PK       Fc    63.0 Hz  Gain  -4.5 dB  Q  2.00\n"));
+    let round_trip = import(&exported).unwrap();
+    assert_eq!(round_trip.filters.len(), 4);
+    assert_eq!(round_trip.filters[2].frequency, 1200.0);
+
+    let apo = export(&EqState { preamp: -3.0, ..eq }, RewEquipment::EqualizerApo).unwrap();
+    assert!(apo.contains("Equaliser: Equalizer APO\nPreamp: -3.0 dB\nFilter 1: ON PK Fc 63.0 Hz Gain -4.5 dB Q 2.000\n"));
+    assert_eq!(import(&apo).unwrap().preamp, -3.0);
+}
+
+#[test]
+fn test_rew_errors() {
+    let e = import("Equaliser: Generic\nFilter  1: ON  LS 6dB  Fc  100 Hz  Gain 3.0 dB\n").unwrap_err();
+    assert_eq!(e.message, "Line 2: Unsupported filter type: LS 6dB");
+    let e = import("Filter  1: ON  PK  Fc  100 Hz  Gain 3.0 dB\n").unwrap_err();
+    assert!(e.message.starts_with("Line 1: Malformed filter line (missing Q"));
+    assert!(import("Filter  1: ON  PK  Fc  abc Hz  Gain 3.0 dB  Q 1\n").is_err());
+    assert!(import("Equaliser: Generic\nFilter  1: None\n").is_err());
+}
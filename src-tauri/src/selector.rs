@@ -0,0 +1,245 @@
+//! Parsing and matching of EqualizerAPO `Device:` selectors.
+//!
+//! APO matches each semicolon-separated pattern case-insensitively against a device string made of
+//! the device name, connection name and endpoint GUID (e.g. `Speakers Realtek Audio {guid}`). A
+//! pattern only has to match part of that string, `*` and `?` act as wildcards and `all` matches
+//! every device.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::DeviceInfo;
+use crate::filters::{self, DeviceFilterMapping, EqState};
+
+const ALL_PATTERN: &str = "all";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeviceSelector {
+    pub patterns: Vec<String>,
+}
+
+impl DeviceSelector {
+
+    /// Parses the text following `Device:`, with or without the prefix and comment marker.
+    pub fn parse(raw: &str) -> DeviceSelector {
+        let trimmed = raw.trim().trim_start_matches('#').trim();
+        let body = match trimmed.strip_prefix("Device:") {
+            Some(rest) => rest,
+            None => trimmed
+        };
+        let patterns = body
+            .split(';')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        DeviceSelector { patterns }
+    }
+
+    /// The selector eq+ uses for a single device: its device string, which matches nothing else.
+    pub fn for_device(device: &DeviceInfo) -> DeviceSelector {
+        DeviceSelector { patterns: vec![device_string(&device.name, &device.guid)] }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.patterns.iter().any(|p| p.eq_ignore_ascii_case(ALL_PATTERN))
+    }
+
+    pub fn matches(&self, device_string: &str) -> bool {
+        let target: Vec<char> = device_string.to_lowercase().chars().collect();
+        self.patterns.iter().any(|p| {
+            if p.eq_ignore_ascii_case(ALL_PATTERN) {
+                return true;
+            }
+            let pattern: Vec<char> = format!("*{}*", p.to_lowercase()).chars().collect();
+            wildcard_match(&pattern, &target)
+        })
+    }
+
+    /// Endpoint GUIDs (the `{...}` parts) the patterns mention.
+    pub fn guids(&self) -> Vec<String> {
+        let mut guids = vec![];
+        for pattern in &self.patterns {
+            let mut rest = pattern.as_str();
+            while let (Some(start), Some(end)) = (rest.find('{'), rest.find('}')) {
+                if end < start {
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                let guid = &rest[start..=end];
+                if !guid.contains(['*', '?']) {
+                    guids.push(guid.to_string());
+                }
+                rest = &rest[end + 1..];
+            }
+        }
+        guids
+    }
+
+    pub fn matches_device(&self, device: &DeviceInfo) -> bool {
+        self.matches(&device_string(&device.name, &device.guid))
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.patterns.join("; "))
+    }
+}
+
+/// The live devices a mapping section applies to.
+#[derive(Serialize, Clone)]
+pub struct SectionMatch {
+    pub section: String,
+    pub selector: DeviceSelector,
+    pub devices: Vec<DeviceInfo>,
+}
+
+pub fn match_sections(mapping: &DeviceFilterMapping, devices: &[DeviceInfo]) -> Vec<SectionMatch> {
+    mapping
+        .keys()
+        .map(|section| {
+            let selector = DeviceSelector::parse(section);
+            let matched = devices.iter().filter(|d| selector.matches_device(d)).cloned().collect();
+            SectionMatch { section: section.clone(), selector, devices: matched }
+        })
+        .collect()
+}
+
+/// The live device a section stands for. Of several matches (as with "all"), a default device
+/// is picked.
+pub fn device_for_section<'a>(section: &str, devices: &'a [DeviceInfo]) -> Option<&'a DeviceInfo> {
+    let selector = DeviceSelector::parse(section);
+    let matched: Vec<&DeviceInfo> = devices.iter().filter(|d| selector.matches_device(d)).collect();
+    matched.iter().find(|d| d.is_default).or(matched.first()).copied()
+}
+
+/// What a device actually gets: the enabled sections applying to it, in evaluation order, and
+/// the single chain they add up to.
+#[derive(Serialize, Clone)]
+pub struct EffectiveChain {
+    pub sections: Vec<String>,
+    pub eq: EqState,
+}
+
+pub fn resolve_chain(mapping: &DeviceFilterMapping, device: &DeviceInfo) -> EffectiveChain {
+    // with Stage lines in the config, sections only run on devices of their own direction
+    let staged = filters::has_capture_sections(mapping);
+    let matching: Vec<(&String, &filters::FilterBank, bool)> = filters::ordered_sections(mapping)
+        .into_iter()
+        .filter(|(_, bank)| bank.enabled)
+        .filter(|(_, bank)| !staged || bank.direction == device.direction)
+        .map(|(name, bank)| (name, bank, DeviceSelector::parse(name)))
+        .filter(|(_, _, selector)| selector.matches_device(device))
+        .map(|(name, bank, selector)| (name, bank, selector.is_all()))
+        .collect();
+    let inherits = matching.iter().all(|(_, bank, is_all)| *is_all || bank.inherit_all);
+
+    let mut sections = vec![];
+    let mut eq = EqState { preamp: 0.0, filters: vec![], graphic_eq: vec![] };
+    for (name, bank, is_all) in matching {
+        if is_all && !inherits {
+            continue;
+        }
+        let flat = bank.flattened();
+        eq.preamp += flat.preamp;
+        eq.filters.extend(flat.filters);
+        eq.graphic_eq = filters::combine_graphic_eq(&[(&eq.graphic_eq, 1.0), (&flat.graphic_eq, 1.0)]);
+        sections.push(name.clone());
+    }
+    for (i, filter) in eq.filters.iter_mut().enumerate() {
+        filter.id = (i + 1).to_string();
+    }
+    EffectiveChain { sections, eq }
+}
+
+/// Builds APO's device string from a Windows friendly name such as `Speakers (Realtek Audio)`.
+pub fn device_string(name: &str, guid: &str) -> String {
+    format!("{} {}", name.replace(['(', ')'], ""), guid)
+}
+
+fn wildcard_match(pattern: &[char], target: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < target.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == target[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[test]
+fn test_device_selector() {
+    let selector = DeviceSelector::parse("#Device: Speakers Realtek;  Headphones* {1234*} ; ");
+    assert_eq!(selector.patterns, vec!["Speakers Realtek".to_string(), "Headphones* {1234*}".to_string()]);
+    assert_eq!(selector.to_string(), "Speakers Realtek; Headphones* {1234*}");
+    assert!(!selector.is_all());
+
+    assert!(selector.matches(&device_string("Speakers (Realtek High Definition Audio)", "{aaaa}")));
+    assert!(selector.matches(&device_string("Headphones (USB DAC)", "{1234-5678}")));
+    assert!(!selector.matches(&device_string("Headphones (USB DAC)", "{5678-1234}")));
+    assert!(!selector.matches(&device_string("Line Out (Realtek High Definition Audio)", "{bbbb}")));
+
+    let all = DeviceSelector::parse("Device: all");
+    assert!(all.is_all());
+    assert!(all.matches("anything {cccc}"));
+
+    let single = DeviceSelector::parse("Device: spea?ers realtek high definition audio {aaaa}");
+    assert!(single.matches(&device_string("Speakers (Realtek High Definition Audio)", "{AAAA}")));
+}
+
+#[test]
+fn test_device_for_section() {
+    use crate::backend::{Direction, MixFormat};
+
+    let device = |guid: &str, name: &str, is_default: bool, sample_rate: u32| DeviceInfo {
+        guid: guid.to_string(), name: name.to_string(), apo_installed: true, is_default, direction: Direction::Render,
+        format: Some(MixFormat { sample_rate, bit_depth: 24, channels: 2, channel_mask: 0x3 }),
+    };
+    let devices = vec![device("{0001}", "Speakers", false, 44100), device("{0002}", "Headphones (USB DAC)", true, 96000)];
+
+    assert_eq!(device_for_section("all", &devices).unwrap().sample_rate(), 96000.0);
+    assert_eq!(device_for_section("Speakers", &devices).unwrap().sample_rate(), 44100.0);
+    assert!(device_for_section("HDMI", &devices).is_none());
+    let unknown = DeviceInfo { format: None, ..devices[0].clone() };
+    assert_eq!(unknown.sample_rate(), crate::response::DEFAULT_SAMPLE_RATE);
+}
+
+#[test]
+fn test_resolve_chain() {
+    let mut mapping = filters::FilterBank::default();
+    let device = DeviceInfo { guid: "{abcd}".to_string(), name: "Headphones (USB DAC)".to_string(), apo_installed: true, is_default: false, direction: crate::backend::Direction::Render, format: None };
+    let section = DeviceSelector::for_device(&device).to_string();
+    mapping.insert(section.clone(), filters::FilterBank::new(EqState { preamp: -2.0, filters: vec![], graphic_eq: vec![] }));
+    mapping.get_mut("all").unwrap().eq_mut().preamp = -1.0;
+
+    let chain = resolve_chain(&mapping, &device);
+    assert_eq!(chain.sections, vec!["all".to_string(), section.clone()]);
+    assert_eq!(chain.eq.preamp, -3.0);
+    assert_eq!(chain.eq.filters.len(), 4);
+
+    mapping.get_mut(&section).unwrap().inherit_all = false;
+    let chain = resolve_chain(&mapping, &device);
+    assert_eq!(chain.sections, vec![section.clone()]);
+    assert_eq!(chain.eq.preamp, -2.0);
+    assert!(chain.eq.filters.is_empty());
+
+    // "all" also reaches microphones until a capture section brings in Stage lines
+    let microphone = DeviceInfo { guid: "{0101}".to_string(), name: "Microphone (USB DAC)".to_string(), direction: crate::backend::Direction::Capture, ..device };
+    assert_eq!(resolve_chain(&mapping, &microphone).sections, vec!["all".to_string()]);
+    let mic_section = DeviceSelector::for_device(&microphone).to_string();
+    mapping.insert(mic_section.clone(), filters::FilterBank { direction: crate::backend::Direction::Capture, ..filters::FilterBank::new(EqState::default()) });
+    assert_eq!(resolve_chain(&mapping, &microphone).sections, vec![mic_section]);
+    assert_eq!(resolve_chain(&mapping, &device).sections, vec![section]);
+}
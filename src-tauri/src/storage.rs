@@ -0,0 +1,71 @@
+//! JSON files kept in the app data directory.
+
+use std::{fs, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::{AppError, ErrorType};
+
+/// Reads a JSON file, falling back to the default value when it does not exist yet.
+pub fn read_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, AppError> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    load_json(path)
+}
+
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> Result<T, AppError> {
+    let raw = fs::read_to_string(path)?;
+    serde_json::from_str(&raw).map_err(|e| {
+        AppError { err_type: ErrorType::InvalidConfig, message: format!("Could not parse {}: {}", path.display(), e) }
+    })
+}
+
+/// Writes a JSON file through a temporary file so a crash never leaves a half-written one behind.
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), AppError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let raw = serde_json::to_string_pretty(value).map_err(|e| {
+        AppError { err_type: ErrorType::GenericIoError, message: format!("Could not serialize {}: {}", path.display(), e) }
+    })?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, raw)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Seconds since the unix epoch, used for the timestamps stored alongside app data.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A scratch directory for tests, removed on drop so a failing assertion does not leave it behind.
+#[cfg(test)]
+pub struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let dir = std::env::temp_dir().join(format!("eqplus-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
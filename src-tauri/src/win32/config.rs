@@ -1,10 +1,8 @@
 use crate::errors::AppError;
+use crate::registry::apo;
 
-use super::registry;
-
-const EQUALIZER_APO_REGISTRY_PATH: &str = "HKEY_LOCAL_MACHINE\\SOFTWARE\\EqualizerAPO\\";
-const EQUALIZER_APO_CONFIG_KEY: &str = "ConfigPath";
+use super::registry::Win32Registry;
 
 pub fn get_equalizer_apo_config_dir() -> Result<String, AppError> {
-    registry::read_value(EQUALIZER_APO_REGISTRY_PATH, EQUALIZER_APO_CONFIG_KEY)
+    apo::config_dir(&Win32Registry)
 }
@@ -1,22 +1,21 @@
 //! adapted from cpal and equalizerapo
 
 use std::{slice, ffi::OsString, os::windows::prelude::OsStringExt};
+use std::sync::{mpsc, Mutex};
 
+use log::warn;
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use windows::core::PCWSTR;
 use windows::Win32::Media::Audio::{self, PKEY_AudioEndpoint_GUID};
-use windows::Win32::Media::Audio::Apo::{PKEY_FX_PreMixEffectClsid, PKEY_FX_PostMixEffectClsid, PKEY_FX_StreamEffectClsid, PKEY_FX_ModeEffectClsid, PKEY_FX_EndpointEffectClsid};
-use windows::Win32::System::Com::{self, CLSIDFromString, StructuredStorage, STGM_READ, VT_LPWSTR};
+use windows::Win32::System::Com::{self, StructuredStorage, STGM_READ, VT_LPWSTR};
 use windows::Win32::Devices::Properties;
 use windows::Win32::UI::Shell::PropertiesSystem::{IPropertyStore, PROPERTYKEY};
-use windows::core::{GUID, HSTRING};
 
+use crate::backend::{DeviceInfo, Direction, MixFormat, WatchSignal};
 use crate::errors::{AppError, ErrorType};
+use crate::registry::apo;
 use super::com;
-use super::registry;
-
-const EQUALIZERAPO_PRE_MIX_GUID: GUID = GUID::from_u128(0xeacd2258_fcac_4ff4_b36d_419e924a6d79);
-const EQUALIZERAPO_POST_MIX_GUID: GUID = GUID::from_u128(0xec1cc9ce_faed_4822_828a_82a81a6f018f);
+use super::registry::Win32Registry;
 
 
 // /// Wrapper because of that stupid decision to remove `Send` and `Sync` from raw pointers.
@@ -50,100 +49,110 @@ unsafe impl Send for Enumerator {}
 unsafe impl Sync for Enumerator {}
 
 
-#[derive(Clone, Serialize)]
-pub struct DeviceInfo {
-    pub guid: String,
-    pub name: String,
-    pub apo_installed: bool,
-    pub is_default: bool,
+pub fn enumerate() -> Result<Vec<DeviceInfo>, AppError> {
+    let mut devices = enumerate_flow(Direction::Render)?;
+    devices.extend(enumerate_flow(Direction::Capture)?);
+    Ok(devices)
 }
 
-impl DeviceInfo {
-    pub fn enumerate() -> Result<Vec<DeviceInfo>, AppError> {
-        unsafe {
-            let default_device = DeviceInfo::get_default_device_guid()?;
+fn data_flow(direction: Direction) -> Audio::EDataFlow {
+    match direction {
+        Direction::Render => Audio::eRender,
+        Direction::Capture => Audio::eCapture,
+    }
+}
 
-            let device_collection = ENUMERATOR
-                .0
-                .EnumAudioEndpoints(Audio::eRender, Audio::DEVICE_STATE_ACTIVE)
-                .map_err(|_| {
-                    AppError{ err_type: ErrorType::RegistryError, message: format!("Failed to enumerate audio endpoints") }
-                })?;
+fn enumerate_flow(direction: Direction) -> Result<Vec<DeviceInfo>, AppError> {
+    unsafe {
+        // plenty of machines have no microphone at all, and then no default capture device either
+        let default_device = match direction {
+            Direction::Render => Some(get_default_device_guid(direction)?),
+            Direction::Capture => get_default_device_guid(direction).ok(),
+        };
 
-            let count = device_collection.GetCount().map_err(|_| {
-                AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to count devices from collection") }
+        let device_collection = ENUMERATOR
+            .0
+            .EnumAudioEndpoints(data_flow(direction), Audio::DEVICE_STATE_ACTIVE)
+            .map_err(|_| {
+                AppError{ err_type: ErrorType::RegistryError, message: format!("Failed to enumerate audio endpoints") }
             })?;
 
-            let mut devices: Vec<DeviceInfo> = Vec::new();
-
-            for i in 0..count {
-                let device = match device_collection.Item(i) {
-                    Ok(d) => d,
-                    Err(_) => continue
-                };
-
-                // Open the device's property store.
-                let property_store = device
-                    .OpenPropertyStore(STGM_READ)
-                    .expect("could not open property store");
-
-                let device_name = read_device_property(&property_store, &Properties::DEVPKEY_Device_FriendlyName as *const _ as *const _)?;
-                let device_guid = read_device_property(&property_store, &PKEY_AudioEndpoint_GUID as *const _ as *const _)?;
-
-                let mut installed = false;
-
-                let key_path = format!("HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{}", device_guid.to_lowercase());
-                for fx_prop_key in APO_FX_GUIDS {
-                    let fx_guid = fx_prop_key_as_string(fx_prop_key)?;
-                    let path = format!("{}{}", key_path, "\\FxProperties");
-                    if registry::value_exists(path.as_str(), fx_guid.as_str()).unwrap_or(false) {
-                        let apo_guid_string = registry::read_value(path.as_str(), fx_guid.as_str())?;
-                        let guid = CLSIDFromString(&HSTRING::from(apo_guid_string)).map_err(|_| {
-                            AppError { err_type: ErrorType::GenericIoError, message: format!("Failed to get GUID from string") }
-                        })?;
-                        if guid == EQUALIZERAPO_PRE_MIX_GUID || guid == EQUALIZERAPO_POST_MIX_GUID {
-                            installed = true;
-                            break;
-                        }
-                    }
-                }
-
-                devices.push(DeviceInfo { guid: device_guid.to_lowercase(), name: device_name, apo_installed: installed, is_default: device_guid == default_device });
+        let count = device_collection.GetCount().map_err(|_| {
+            AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to count devices from collection") }
+        })?;
+
+        let mut devices: Vec<DeviceInfo> = Vec::new();
+
+        for i in 0..count {
+            let device = match device_collection.Item(i) {
+                Ok(d) => d,
+                Err(_) => continue
+            };
+
+            // a device going away while we enumerate can no longer be read, so it is skipped
+            match read_endpoint(&device, direction, default_device.as_deref()) {
+                Ok(info) => devices.push(info),
+                Err(e) => warn!("Skipping audio endpoint {}: {}", i, e.message),
             }
-            return Ok(devices);
         }
+        return Ok(devices);
     }
+}
 
-    fn get_default_device_guid() -> Result<String, AppError> {
-        unsafe {
-            let default_device = ENUMERATOR
-                .0
-                .GetDefaultAudioEndpoint(Audio::eRender, Audio::eConsole)
-                .map_err(|_| {
-                    AppError{ err_type: ErrorType::RegistryError, message: format!("Failed to get default audio endpoint") }
-                })?;
-
-            let property_store = default_device
-                .OpenPropertyStore(STGM_READ)
-                .expect("could not open property store");
-
-            let device_guid = read_device_property(&property_store, &PKEY_AudioEndpoint_GUID as *const _ as *const _)?;
-            Ok(device_guid)
-        }
+fn read_endpoint(device: &Audio::IMMDevice, direction: Direction, default_device: Option<&str>) -> Result<DeviceInfo, AppError> {
+    let property_store = open_property_store(device)?;
+
+    let device_name = read_device_property(&property_store, &Properties::DEVPKEY_Device_FriendlyName as *const _ as *const _)?;
+    let device_guid = read_device_property(&property_store, &PKEY_AudioEndpoint_GUID as *const _ as *const _)?;
+
+    let installed = apo::apo_installed(&Win32Registry, &device_guid, direction)?;
+    let is_default = default_device == Some(device_guid.as_str());
+    let format = get_mix_format(device).ok();
+
+    Ok(DeviceInfo { guid: device_guid.to_lowercase(), name: device_name, apo_installed: installed, is_default, direction, format })
+}
+
+fn get_default_device_guid(direction: Direction) -> Result<String, AppError> {
+    unsafe {
+        let default_device = ENUMERATOR
+            .0
+            .GetDefaultAudioEndpoint(data_flow(direction), Audio::eConsole)
+            .map_err(|_| {
+                AppError{ err_type: ErrorType::RegistryError, message: format!("Failed to get default audio endpoint") }
+            })?;
+
+        let property_store = open_property_store(&default_device)?;
+
+        let device_guid = read_device_property(&property_store, &PKEY_AudioEndpoint_GUID as *const _ as *const _)?;
+        Ok(device_guid)
+    }
+}
+
+fn open_property_store(device: &Audio::IMMDevice) -> Result<IPropertyStore, AppError> {
+    unsafe {
+        device
+            .OpenPropertyStore(STGM_READ)
+            .map_err(|err| AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to open property store: {}", err) })
     }
 }
 
-const APO_FX_GUIDS: &'static [PROPERTYKEY] = &[
-    PKEY_FX_PreMixEffectClsid,
-    PKEY_FX_PostMixEffectClsid,
-    PKEY_FX_StreamEffectClsid,
-    PKEY_FX_ModeEffectClsid,
-    PKEY_FX_EndpointEffectClsid
-];
-
-fn fx_prop_key_as_string(prop_key: &PROPERTYKEY) -> Result<String, AppError> {
-        let s = registry::get_guid_string(prop_key.fmtid)?;
-        Ok(format!("{},{}", s.to_lowercase(), prop_key.pid))
+/// The shared-mode format the audio engine mixes the device's streams in.
+fn get_mix_format(device: &Audio::IMMDevice) -> Result<MixFormat, AppError> {
+    unsafe {
+        let client = device
+            .Activate::<Audio::IAudioClient>(Com::CLSCTX_ALL, None)
+            .map_err(|err| AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to activate audio client: {}", err) })?;
+        let wave_format = client
+            .GetMixFormat()
+            .map_err(|err| AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to get mix format: {}", err) })?;
+
+        // cbSize counts the bytes following the WAVEFORMATEX header, e.g. the extensible part
+        let size = std::mem::size_of::<Audio::WAVEFORMATEX>() + (*wave_format).cbSize as usize;
+        let format = MixFormat::from_wave_format(slice::from_raw_parts(wave_format as *const u8, size));
+        Com::CoTaskMemFree(Some(wave_format as *const _));
+
+        format.ok_or(AppError{ err_type: ErrorType::GenericIoError, message: "Mix format was invalid".to_string() })
+    }
 }
 
 fn read_device_property(store: &IPropertyStore, key: *const PROPERTYKEY) -> Result<String, AppError> {
@@ -188,14 +197,74 @@ fn read_device_property(store: &IPropertyStore, key: *const PROPERTYKEY) -> Resu
     }
 }
 
-// pub fn register_device_change_callback() {
-//     unsafe {
-//         ENUMERATOR.0.RegisterEndpointNotificationCallback(pclient)
-//     }
-// }
+/// Registers for endpoint notifications, which then wake the device watcher through `signals`.
+/// The returned closure unregisters again.
+pub fn register_notifications(signals: mpsc::Sender<WatchSignal>) -> Result<impl FnOnce() + Send, AppError> {
+    let client: Audio::IMMNotificationClient = NotificationClient { signals: Mutex::new(signals) }.into();
+    unsafe {
+        ENUMERATOR
+            .0
+            .RegisterEndpointNotificationCallback(&client)
+            .map_err(|err| AppError{ err_type: ErrorType::GenericIoError, message: format!("Failed to register for device notifications: {}", err) })?;
+    }
+    let client = RegisteredClient(client);
+    Ok(move || client.unregister())
+}
+
+/// Passes endpoint notifications on to the device watcher. The callbacks run on a thread of the
+/// audio service and must not call back into the MMDevice API, so they only signal the watcher,
+/// which enumerates again on its own thread.
+#[windows::core::implement(Audio::IMMNotificationClient)]
+struct NotificationClient {
+    signals: Mutex<mpsc::Sender<WatchSignal>>,
+}
+
+impl NotificationClient {
+    fn changed(&self) -> windows::core::Result<()> {
+        // the watcher is gone once it was stopped, until the client is unregistered right after
+        let _ = self.signals.lock().unwrap().send(WatchSignal::Changed);
+        Ok(())
+    }
+}
+
+#[allow(non_snake_case)]
+impl Audio::IMMNotificationClient_Impl for NotificationClient {
+    fn OnDeviceStateChanged(&self, _device_id: &PCWSTR, _new_state: u32) -> windows::core::Result<()> {
+        self.changed()
+    }
 
-// struct DeviceNotificationClient {}
+    fn OnDeviceAdded(&self, _device_id: &PCWSTR) -> windows::core::Result<()> {
+        self.changed()
+    }
+
+    fn OnDeviceRemoved(&self, _device_id: &PCWSTR) -> windows::core::Result<()> {
+        self.changed()
+    }
 
-// impl DeviceNotificationClient {
-//     pub unsafe fn OnDefaultDeviceChanged(&)
-// }
+    fn OnDefaultDeviceChanged(&self, _flow: Audio::EDataFlow, role: Audio::ERole, _device_id: &PCWSTR) -> windows::core::Result<()> {
+        // the other roles have defaults of their own, but eq+ only reports the console one
+        if role == Audio::eConsole {
+            return self.changed();
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _device_id: &PCWSTR, _key: &PROPERTYKEY) -> windows::core::Result<()> {
+        self.changed()
+    }
+}
+
+/// Send wrapper so the client can be unregistered from whichever thread stops the watcher.
+struct RegisteredClient(Audio::IMMNotificationClient);
+
+unsafe impl Send for RegisteredClient {}
+
+impl RegisteredClient {
+    fn unregister(self) {
+        unsafe {
+            if let Err(err) = ENUMERATOR.0.UnregisterEndpointNotificationCallback(&self.0) {
+                warn!("Failed to unregister device notifications: {}", err);
+            }
+        }
+    }
+}
@@ -1,4 +1,40 @@
 pub mod device;
 pub mod registry;
 pub mod com;
-pub mod config;
\ No newline at end of file
+pub mod config;
+
+use std::sync::Arc;
+
+use crate::backend::{self, AudioBackend, DeviceChangeCallback, DeviceInfo, Watcher};
+use crate::errors::AppError;
+use crate::registry::apo::{self, FxSlot};
+
+use registry::Win32Registry;
+
+/// The real thing: MMDevice enumeration and EqualizerAPO's registry keys.
+pub struct Win32Backend;
+
+impl AudioBackend for Win32Backend {
+    fn name(&self) -> &'static str {
+        "windows"
+    }
+
+    fn config_dir(&self) -> Result<String, AppError> {
+        config::get_equalizer_apo_config_dir()
+    }
+
+    fn enumerate(&self) -> Result<Vec<DeviceInfo>, AppError> {
+        device::enumerate()
+    }
+
+    fn apo_slots(&self, device: &DeviceInfo) -> Result<Option<Vec<FxSlot>>, AppError> {
+        Ok(Some(apo::apo_slots(&Win32Registry, &device.guid, device.direction)?))
+    }
+
+    /// Endpoint notifications wake the watcher instead of polling.
+    fn watch(self: Arc<Self>, on_change: DeviceChangeCallback) -> Result<Watcher, AppError> {
+        let (watcher, signals) = backend::watch_device_list(self, on_change, None)?;
+        let unregister = device::register_notifications(signals)?;
+        Ok(watcher.and_then(unregister))
+    }
+}
@@ -1,9 +1,22 @@
 use windows::Win32::System::Registry::{HKEY, RegCloseKey, RegOpenKeyExW, RegQueryValueExW, HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS, REG_SAM_FLAGS, KEY_QUERY_VALUE, KEY_WOW64_64KEY, REG_VALUE_TYPE, REG_SZ};
-use windows::Win32::System::Com::StringFromCLSID;
 use windows::Win32::Foundation::MAX_PATH;
-use windows::core::{GUID, HSTRING};
+use windows::core::HSTRING;
 
 use crate::errors::{ErrorType, AppError};
+use crate::registry::{RegistryReader, RootKey};
+
+/// The live registry, through the free functions below.
+pub struct Win32Registry;
+
+impl RegistryReader for Win32Registry {
+    fn read_value(&self, key: &str, value: &str) -> Result<String, AppError> {
+        read_value(key, value)
+    }
+
+    fn value_exists(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        value_exists(key, value)
+    }
+}
 
 pub fn read_value(key: &str, value: &str) -> Result<String, AppError> {
     let key_handle = open_key(key, KEY_QUERY_VALUE | KEY_WOW64_64KEY)?;
@@ -49,15 +62,6 @@ pub fn value_exists(key: &str, value: &str) -> Result<bool, AppError> {
 //     }
 // }
 
-pub fn get_guid_string(guid: GUID) -> Result<String, AppError> {
-    unsafe {
-        let result = StringFromCLSID(&guid).map_err(|_| {
-            AppError { err_type: ErrorType::RegistryError, message: format!("Failed to convert GUID to string" )}
-        })?;
-        return Ok(result.to_string().unwrap());
-    }
-}
-
 pub fn open_key(key: &str, sam_desired: REG_SAM_FLAGS) -> Result<HKEY, AppError> {
     let (sub_key, root_key) = split_key(key)?;
 
@@ -72,22 +76,13 @@ pub fn open_key(key: &str, sam_desired: REG_SAM_FLAGS) -> Result<HKEY, AppError>
 }
 
 pub fn split_key(key: &str) -> Result<(String, HKEY), AppError> {
-    let pos = match key.find("\\") {
-        Some(t) => t,
-        None => return Err(AppError { err_type: ErrorType::RegistryError, message: format!("Registry key was an invalid format: {}", key) })
-    };
-
-    let root_part = &key[0..pos];
-    let path_part = &key[pos+1..];
-
-    let p = root_part.to_uppercase();
-    let root_key = match p.as_str() {
-        "HKEY_CLASSES_ROOT" => HKEY_CLASSES_ROOT,
-        "HKEY_CURRENT_CONFIG" => HKEY_CURRENT_CONFIG,
-        "HKEY_CURRENT_USER" => HKEY_CURRENT_USER,
-        "HKEY_LOCAL_MACHINE" => HKEY_LOCAL_MACHINE,
-        "HKEY_USERS" => HKEY_USERS,
-        _ => return Err(AppError { err_type: ErrorType::RegistryError, message: format!("Unexpected root key {}", root_part) })
+    let (path_part, root) = crate::registry::split_key(key)?;
+    let root_key = match root {
+        RootKey::ClassesRoot => HKEY_CLASSES_ROOT,
+        RootKey::CurrentConfig => HKEY_CURRENT_CONFIG,
+        RootKey::CurrentUser => HKEY_CURRENT_USER,
+        RootKey::LocalMachine => HKEY_LOCAL_MACHINE,
+        RootKey::Users => HKEY_USERS,
     };
-    return Ok((String::from(path_part), root_key));
+    return Ok((path_part, root_key));
 }
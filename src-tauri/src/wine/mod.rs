@@ -0,0 +1,190 @@
+//! Backend for EqualizerAPO-compatible setups running inside a Wine prefix.
+//!
+//! Everything is read from the prefix's registry files, so it works without Wine running. Keys use
+//! the same root-qualified form as every other `RegistryReader` (`HKEY_LOCAL_MACHINE\SOFTWARE\...`).
+
+pub mod paths;
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{AudioBackend, DeviceInfo, Direction, MixFormat};
+use crate::errors::{AppError, ErrorType};
+use crate::registry::hive::{Hive, KeyNames, RegValue};
+use crate::registry::apo::{self, FxSlot};
+use crate::registry::{self, RegistryReader, RootKey};
+
+pub const WINEPREFIX_ENV: &str = "WINEPREFIX";
+
+const DEVICE_STATE_ACTIVE: u32 = 1;
+const PKEY_DEVICE_FRIENDLY_NAME: &str = "{a45c254e-df1c-4efd-8020-67d146a850e0},14";
+const PKEY_DEVICE_DESC: &str = "{a45c254e-df1c-4efd-8020-67d146a850e0},2";
+const PKEY_AUDIO_ENGINE_DEVICE_FORMAT: &str = "{f19f064d-082c-4e27-bc73-6882a1bb8e4c},0";
+const VT_BLOB: u32 = 0x41;
+
+/// The registry of a prefix: `system.reg` is HKEY_LOCAL_MACHINE, `user.reg` HKEY_CURRENT_USER.
+pub struct WineRegistry {
+    machine: Hive,
+    user: Hive,
+}
+
+impl WineRegistry {
+    pub fn load(prefix: &Path) -> Result<WineRegistry, AppError> {
+        let user = prefix.join("user.reg");
+        Ok(WineRegistry {
+            machine: Hive::load(&prefix.join("system.reg"), KeyNames::Escaped)?,
+            user: if user.exists() { Hive::load(&user, KeyNames::Escaped)? } else { Hive::default() },
+        })
+    }
+
+    pub fn value(&self, key: &str, value: &str) -> Result<Option<&RegValue>, AppError> {
+        let (hive, sub_key) = self.hive(key)?;
+        Ok(hive.value(&sub_key, value))
+    }
+
+    pub fn subkeys(&self, key: &str) -> Result<Vec<String>, AppError> {
+        let (hive, sub_key) = self.hive(key)?;
+        Ok(hive.subkeys(&sub_key))
+    }
+
+    /// The hive holding `key` and the path within it. Wine keeps no other roots in files.
+    fn hive(&self, key: &str) -> Result<(&Hive, String), AppError> {
+        match self.split_key(key)? {
+            (path, RootKey::LocalMachine) => Ok((&self.machine, path)),
+            (path, RootKey::CurrentUser) => Ok((&self.user, path)),
+            (_, root) => Err(AppError { err_type: ErrorType::RegistryError, message: format!("Unexpected root key {}", root.name()) }),
+        }
+    }
+}
+
+impl RegistryReader for WineRegistry {
+    fn read_value(&self, key: &str, value: &str) -> Result<String, AppError> {
+        registry::read_string(self.value(key, value)?)
+    }
+
+    fn value_exists(&self, key: &str, value: &str) -> Result<bool, AppError> {
+        Ok(self.value(key, value)?.is_some())
+    }
+}
+
+pub struct WineBackend {
+    prefix: PathBuf,
+}
+
+impl WineBackend {
+    /// Uses `$WINEPREFIX`, or `~/.wine`.
+    pub fn new() -> Result<WineBackend, AppError> {
+        let prefix = match env::var_os(WINEPREFIX_ENV).filter(|v| !v.is_empty()) {
+            Some(prefix) => PathBuf::from(prefix),
+            None => env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".wine"))
+                .ok_or(AppError { err_type: ErrorType::InvalidConfigDirectory, message: "Neither WINEPREFIX nor HOME is set".to_string() })?,
+        };
+        Ok(WineBackend::with_prefix(prefix))
+    }
+
+    pub fn with_prefix(prefix: PathBuf) -> WineBackend {
+        WineBackend { prefix }
+    }
+
+    fn registry(&self) -> Result<WineRegistry, AppError> {
+        WineRegistry::load(&self.prefix)
+    }
+}
+
+impl AudioBackend for WineBackend {
+    fn name(&self) -> &'static str {
+        "wine"
+    }
+
+    fn config_dir(&self) -> Result<String, AppError> {
+        let config_path = apo::config_dir(&self.registry()?)?;
+        Ok(paths::to_host_path(&self.prefix, &config_path)?.to_string_lossy().to_string())
+    }
+
+    fn enumerate(&self) -> Result<Vec<DeviceInfo>, AppError> {
+        devices(&self.registry()?)
+    }
+
+    fn apo_slots(&self, device: &DeviceInfo) -> Result<Option<Vec<FxSlot>>, AppError> {
+        Ok(Some(apo::apo_slots(&self.registry()?, &device.guid, device.direction)?))
+    }
+}
+
+/// Active render and capture endpoints. Wine keeps the default devices outside the MMDevices
+/// keys, so no device is reported as default.
+fn devices(registry: &WineRegistry) -> Result<Vec<DeviceInfo>, AppError> {
+    let mut devices = vec![];
+    for direction in Direction::ALL {
+        for guid in registry.subkeys(apo::endpoints_key(direction))? {
+            if let Some(device) = device(registry, &guid, direction)? {
+                devices.push(device);
+            }
+        }
+    }
+    Ok(devices)
+}
+
+fn device(registry: &WineRegistry, guid: &str, direction: Direction) -> Result<Option<DeviceInfo>, AppError> {
+    let key = apo::device_key(guid, direction);
+    if registry.value(&key, "DeviceState")? != Some(&RegValue::Dword(DEVICE_STATE_ACTIVE)) {
+        return Ok(None);
+    }
+    let properties = format!("{}\\Properties", key);
+    let name = [PKEY_DEVICE_FRIENDLY_NAME, PKEY_DEVICE_DESC]
+        .iter()
+        .find_map(|pkey| registry.read_value(&properties, pkey).ok())
+        .unwrap_or(guid.to_string());
+    let format = match registry.value(&properties, PKEY_AUDIO_ENGINE_DEVICE_FORMAT)? {
+        Some(RegValue::Binary(blob)) => MixFormat::from_wave_format(strip_blob_header(blob)),
+        _ => None,
+    };
+    Ok(Some(DeviceInfo {
+        guid: guid.to_lowercase(),
+        name,
+        apo_installed: apo::apo_installed(registry, guid, direction)?,
+        is_default: false,
+        direction,
+        format,
+    }))
+}
+
+/// Windows itself stores blob properties behind their PROPVARIANT type and size, Wine stores
+/// just the bytes.
+fn strip_blob_header(blob: &[u8]) -> &[u8] {
+    let u32_at = |i: usize| u32::from_le_bytes([blob[i], blob[i + 1], blob[i + 2], blob[i + 3]]);
+    if blob.len() >= 8 && u32_at(0) == VT_BLOB && u32_at(4) as usize == blob.len() - 8 {
+        &blob[8..]
+    } else {
+        blob
+    }
+}
+
+#[test]
+fn test_wine_backend() {
+    use std::fs;
+
+    let prefix = crate::storage::TestDir::new("wine");
+    fs::create_dir_all(prefix.join("drive_c").join("Program Files").join("EqualizerAPO").join("config")).unwrap();
+    fs::write(prefix.join("system.reg"), crate::registry::hive::TEST_SYSTEM_REG).unwrap();
+    let backend = WineBackend::with_prefix(prefix.to_path_buf());
+
+    assert_eq!(
+        backend.config_dir().unwrap(),
+        prefix.join("drive_c").join("Program Files").join("EqualizerAPO").join("config").to_string_lossy()
+    );
+    let devices = backend.enumerate().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].name, "Speakers (Wine PulseAudio)");
+    assert_eq!(devices[0].guid, "{0a1b2c3d-0000-4000-8000-000000000001}");
+    assert!(devices[0].apo_installed);
+    assert_eq!(backend.apo_slots(&devices[0]).unwrap(), Some(vec![FxSlot::PreMix]));
+    assert_eq!(devices[0].format, Some(MixFormat { sample_rate: 48000, bit_depth: 24, channels: 2, channel_mask: 0x3 }));
+    assert_eq!(devices[1].name, "Microphone (Wine PulseAudio)");
+    assert_eq!(devices[1].direction, Direction::Capture);
+    assert!(!devices[1].apo_installed);
+    assert_eq!(devices[1].format.unwrap().sample_rate, 44100);
+
+    fs::remove_dir_all(&*prefix).unwrap();
+    assert!(backend.config_dir().is_err());
+}
@@ -0,0 +1,67 @@
+//! Windows paths inside a Wine prefix to host paths.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{AppError, ErrorType};
+
+/// Translates e.g. `C:\Program Files\EqualizerAPO\config` to
+/// `~/.wine/drive_c/Program Files/EqualizerAPO/config`.
+///
+/// Drives are looked up in the prefix's `dosdevices` links, falling back to Wine's defaults
+/// (`c:` is `drive_c`, `z:` is `/`). Windows paths are case-insensitive, so every component is
+/// matched case-insensitively against what exists on disk.
+pub fn to_host_path(prefix: &Path, windows_path: &str) -> Result<PathBuf, AppError> {
+    let path = windows_path.trim().trim_start_matches("\\\\?\\");
+    let mut chars = path.chars();
+    let (drive, colon) = (chars.next(), chars.next());
+    let drive = match (drive, colon) {
+        (Some(d), Some(':')) if d.is_ascii_alphabetic() => d.to_ascii_lowercase(),
+        _ => return Err(AppError { err_type: ErrorType::InvalidConfigDirectory, message: format!("Not an absolute Windows path: {}", windows_path) }),
+    };
+
+    let link = prefix.join("dosdevices").join(format!("{}:", drive));
+    let mut host = if link.exists() {
+        link
+    } else {
+        match drive {
+            'c' => prefix.join("drive_c"),
+            'z' => PathBuf::from("/"),
+            _ => return Err(AppError { err_type: ErrorType::InvalidConfigDirectory, message: format!("Drive {}: is not mapped in {}", drive, prefix.display()) }),
+        }
+    };
+    for component in path[2..].split(['\\', '/']).filter(|c| !c.is_empty()) {
+        host = match_component(&host, component);
+    }
+    Ok(host)
+}
+
+fn match_component(dir: &Path, component: &str) -> PathBuf {
+    let exact = dir.join(component);
+    if exact.exists() {
+        return exact;
+    }
+    fs::read_dir(dir)
+        .ok()
+        .and_then(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .find(|e| e.file_name().to_string_lossy().eq_ignore_ascii_case(component))
+                .map(|e| e.path())
+        })
+        .unwrap_or(exact)
+}
+
+#[test]
+fn test_to_host_path() {
+    let prefix = crate::storage::TestDir::new("wine-paths");
+    fs::create_dir_all(prefix.join("drive_c").join("Program Files").join("EqualizerAPO")).unwrap();
+
+    assert_eq!(
+        to_host_path(&prefix, "C:\\program files\\EqualizerAPO\\config").unwrap(),
+        prefix.join("drive_c").join("Program Files").join("EqualizerAPO").join("config")
+    );
+    assert_eq!(to_host_path(&prefix, "Z:\\home\\user").unwrap(), PathBuf::from("/home/user"));
+    assert!(to_host_path(&prefix, "D:\\music").is_err());
+    assert!(to_host_path(&prefix, "config").is_err());
+}
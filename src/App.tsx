@@ -5,10 +5,11 @@ import { CanvasPlot, CanvasPlotProps } from './components/CanvasPlot';
 import { DEFAULT_THEMES } from './defaults';
 import { DisplayFilterNode, FilterChanges, FilterParams } from './types/filter';
 import { invoke } from '@tauri-apps/api';
+import { listen } from '@tauri-apps/api/event';
 import { DeviceFilterMapping } from './types/eqstate';
 import isDefined from './utils/isDefined';
 import throttle from './utils/throttle';
-import { DeviceInfo, deviceName } from './types/device';
+import { DeviceEvent, DeviceInfo, deviceName, speakerPositions } from './types/device';
 import { debug, info } from './utils/logBridge';
 import { HBox } from './components/FlexBox';
 import DrawerControls from './components/DrawerControls';
@@ -64,11 +65,20 @@ invoke('query_devices')
       info(`Name: ${device.name}`);
       info(`  GUID: ${device.guid}`);
       info(`  APO installed: ${device.apo_installed}`);
+      info(`  Direction: ${device.direction}`);
       info(`  Is default: ${device.is_default}`)
+      if (device.format) {
+        const { sample_rate, bit_depth } = device.format;
+        info(`  Format: ${sample_rate} Hz, ${bit_depth} bit, ${speakerPositions(device.format).join(' ')}`);
+      }
       info(`  Cleaned up name: ${deviceName(device)}`);
     });
   });
 
+listen<DeviceEvent>('device-event', ({ payload }) => {
+  info(`Device event: ${payload.kind} ${payload.device ? deviceName(payload.device) : '(no device)'}`);
+});
+
 function App() {
   const [ theme ] = useState<DefaultTheme>(DEFAULT_THEMES[0]);
   const [ filters, setFilters ] = useState<FilterParams[]>([]);
@@ -80,7 +90,8 @@ function App() {
       .then(res => {
         const state = res as DeviceFilterMapping;
         debug(JSON.stringify(state, null, 2));
-        setFilters(state['all']?.eq?.filters ?? []);
+        const bank = state['all'];
+        setFilters(bank?.layers[bank.selected_layer]?.eq?.filters ?? []);
       });
   }, []);
 
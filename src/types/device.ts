@@ -1,8 +1,55 @@
+export type MixFormat = {
+  sample_rate: number,
+  bit_depth: number,
+  channels: number,
+  channel_mask: number
+};
+
 export type DeviceInfo = {
   name: string,
   guid: string,
   apo_installed: boolean,
-  is_default: boolean
+  is_default: boolean,
+  direction: 'render' | 'capture',
+  format: MixFormat | null
+};
+
+// SPEAKER_* bits of a WAVEFORMATEXTENSIBLE channel mask, lowest first
+const SPEAKER_POSITIONS = [
+  'FL', 'FR', 'FC', 'LFE', 'BL', 'BR', 'FLC', 'FRC', 'BC', 'SL', 'SR', 'TC', 'TFL', 'TFC', 'TFR', 'TBL', 'TBC', 'TBR'
+];
+
+export function speakerPositions(format: MixFormat) {
+  return SPEAKER_POSITIONS.filter((_, bit) => (format.channel_mask & (1 << bit)) !== 0).slice(0, format.channels);
+}
+
+export type FxSlot = 'pre_mix' | 'post_mix' | 'sfx' | 'mfx' | 'efx';
+
+export type DiagnosisIssue = {
+  kind: 'not_installed' | 'pre_mix_only' | 'config_dir_not_writable' | 'config_path_unreadable' | 'config_path_mismatch',
+  message: string,
+  hint: string
+};
+
+export type DeviceDiagnosis = {
+  device: DeviceInfo,
+  fx_slots: FxSlot[] | null,
+  config_dir: string,
+  config_dir_writable: boolean,
+  apo_config_dir: string | null,
+  issues: DiagnosisIssue[]
+};
+
+export type DeviceEvent =
+  | { kind: 'added', device: DeviceInfo }
+  | { kind: 'removed', device: DeviceInfo }
+  | { kind: 'state_changed', device: DeviceInfo }
+  | { kind: 'default_changed', direction: 'render' | 'capture', device: DeviceInfo | null };
+
+// payload of the relink-skipped event: sections left on a renamed device's old name
+export type SkippedRelink = {
+  from: string,
+  to: string
 };
 
 export function deviceName(info: DeviceInfo) {
@@ -1,14 +1,29 @@
 import { FilterParams } from './filter';
 
+export type GraphicEqPoint = {
+  frequency: number,
+  gain: number
+};
+
 export type EQState = {
   filters: FilterParams[],
-  preamp: number
+  preamp: number,
+  graphic_eq?: GraphicEqPoint[]
 };
 
-export type FilterBank = {
-  device: string,
+export type Layer = {
+  name: string,
   enabled: boolean,
+  gain: number,
   eq: EQState
 };
 
+export type FilterBank = {
+  enabled: boolean,
+  layers: Layer[],
+  selected_layer: number,
+  inherit_all: boolean,
+  direction: 'render' | 'capture'
+};
+
 export type DeviceFilterMapping = Record<string, FilterBank>;